@@ -0,0 +1,226 @@
+use clap::Command;
+use serde_json::Value;
+
+use crate::{extract_value, ALIASES_FIELD, DEFAULT_COMMAND_FIELD};
+
+/// Returns the aliases defined in the configuration file, in file order.
+pub fn aliases(config: &Value) -> Result<Vec<(String, String)>, String> {
+    let aliases = match config.get(ALIASES_FIELD) {
+        Some(Value::Object(aliases)) => aliases,
+        Some(_) => return Err(format!("{ALIASES_FIELD} must map alias names to commands")),
+        None => return Ok(Vec::new()),
+    };
+
+    aliases
+        .iter()
+        .map(|(name, expansion)| match extract_value(expansion) {
+            Some(expansion) => Ok((name.clone(), expansion)),
+            None => Err(format!("Alias {name} must expand to a string")),
+        })
+        .collect()
+}
+
+pub fn default_command(config: &Value) -> Option<String> {
+    config.get(DEFAULT_COMMAND_FIELD).and_then(extract_value)
+}
+
+/// Whether `arg` is an option of `cli` whose value is the next argument.
+fn takes_separate_value(arg: &str, cli: &Command) -> bool {
+    if arg.contains('=') {
+        return false;
+    }
+
+    cli.get_arguments().any(|option| {
+        let named = match arg.strip_prefix("--") {
+            Some(long) => option.get_long() == Some(long),
+            None => arg.len() == 2 && option.get_short() == arg.chars().nth(1),
+        };
+        named && option.get_action().takes_values()
+    })
+}
+
+/// Index of the subcommand in `args`, skipping the global options before it,
+/// or if there is none, the index at which one would have to be inserted.
+fn command_position(args: &[String], cli: &Command) -> Result<usize, usize> {
+    let mut index = 1;
+    while index < args.len() {
+        let arg = &args[index];
+        if arg == "--" {
+            return Err(index);
+        }
+        if !arg.starts_with('-') {
+            return Ok(index);
+        }
+        if takes_separate_value(arg, cli) {
+            index += 1;
+        }
+        index += 1;
+    }
+    Err(args.len().min(index))
+}
+
+/// Expands the alias used as the subcommand in `args`, or inserts the
+/// default command if there is none, before clap sees the arguments.
+/// Expansions are split on whitespace and may use other aliases, but not
+/// themselves, and aliases may not shadow built-in subcommands.
+pub fn expand(mut args: Vec<String>, cli: &Command, config: &Value) -> Result<Vec<String>, String> {
+    let aliases = aliases(config)?;
+
+    for (name, _) in &aliases {
+        let shadows = name == "help"
+            || cli.get_subcommands().any(|command| {
+                command.get_name() == name || command.get_all_aliases().any(|alias| alias == name)
+            });
+        if shadows {
+            return Err(format!("Alias {name} shadows a built-in command"));
+        }
+    }
+
+    let wants_help = args
+        .iter()
+        .any(|arg| matches!(arg.as_str(), "-h" | "--help" | "-V" | "--version"));
+    let mut used_default = false;
+    let mut expanded: Vec<String> = Vec::new();
+
+    loop {
+        let index = match command_position(&args, cli) {
+            Ok(index) => index,
+            Err(end) => match default_command(config) {
+                Some(default) if !used_default && !wants_help => {
+                    used_default = true;
+                    args.splice(end..end, default.split_whitespace().map(String::from));
+                    continue;
+                }
+                _ => return Ok(args),
+            },
+        };
+
+        let name = args[index].clone();
+        let expansion = match aliases.iter().find(|(alias, _)| *alias == name) {
+            Some((_, expansion)) => expansion,
+            None => return Ok(args),
+        };
+        if let Some(start) = expanded.iter().position(|alias| *alias == name) {
+            let cycle = expanded[start..].join(" -> ");
+            return Err(format!("Aliases expand in a cycle: {cycle} -> {name}"));
+        }
+
+        args.splice(
+            index..=index,
+            expansion.split_whitespace().map(String::from),
+        );
+        expanded.push(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+    use serde_json::json;
+
+    use super::*;
+    use crate::Cli;
+
+    fn expand_with(config: Value, args: &[&str]) -> Result<Vec<String>, String> {
+        let args = args.iter().map(|arg| arg.to_string()).collect();
+        expand(args, &Cli::command(), &config)
+    }
+
+    #[test]
+    fn expands_aliases_in_place() {
+        let config = json!({ "ALIASES": {
+            "rv": "revisions --latest",
+            "last": "rv",
+        }});
+
+        assert_eq!(
+            expand_with(
+                config.clone(),
+                &["rustyx", "--timeout", "5m", "rv", "/a.txt"]
+            )
+            .unwrap(),
+            [
+                "rustyx",
+                "--timeout",
+                "5m",
+                "revisions",
+                "--latest",
+                "/a.txt"
+            ]
+        );
+        assert_eq!(
+            expand_with(config.clone(), &["rustyx", "last", "/a.txt"]).unwrap(),
+            ["rustyx", "revisions", "--latest", "/a.txt"]
+        );
+        assert_eq!(
+            expand_with(config, &["rustyx", "whoami"]).unwrap(),
+            ["rustyx", "whoami"]
+        );
+    }
+
+    #[test]
+    fn names_alias_cycles() {
+        let config = json!({ "ALIASES": { "a": "b --x", "b": "a" } });
+        let error = expand_with(config, &["rustyx", "a"]).unwrap_err();
+        assert!(error.contains("a -> b -> a"), "{error}");
+
+        let config = json!({ "ALIASES": { "a": "a --x" } });
+        let error = expand_with(config, &["rustyx", "a"]).unwrap_err();
+        assert!(error.contains("a -> a"), "{error}");
+    }
+
+    #[test]
+    fn refuses_aliases_shadowing_commands() {
+        for name in ["whoami", "help"] {
+            let config = json!({ "ALIASES": { name: "resolve /a" } });
+            let error = expand_with(config, &["rustyx"]).unwrap_err();
+            assert!(error.contains("shadows"), "{error}");
+        }
+    }
+
+    #[test]
+    fn inserts_the_default_command() {
+        let config = json!({
+            "ALIASES": { "me": "whoami --shell-export" },
+            "DEFAULT_COMMAND": "me",
+        });
+
+        assert_eq!(
+            expand_with(config.clone(), &["rustyx"]).unwrap(),
+            ["rustyx", "whoami", "--shell-export"]
+        );
+        assert_eq!(
+            expand_with(config.clone(), &["rustyx", "--quiet"]).unwrap(),
+            ["rustyx", "--quiet", "whoami", "--shell-export"]
+        );
+        assert_eq!(
+            expand_with(config.clone(), &["rustyx", "--"]).unwrap(),
+            ["rustyx", "whoami", "--shell-export", "--"]
+        );
+        assert_eq!(
+            expand_with(config.clone(), &["rustyx", "--help"]).unwrap(),
+            ["rustyx", "--help"]
+        );
+        assert_eq!(
+            expand_with(config, &["rustyx", "logout"]).unwrap(),
+            ["rustyx", "logout"]
+        );
+    }
+
+    #[test]
+    fn reads_aliases_in_file_order() {
+        let config: Value =
+            serde_json::from_str(r#"{"ALIASES": {"z": "whoami", "a": "logout"}}"#).unwrap();
+        assert_eq!(
+            aliases(&config).unwrap(),
+            [
+                ("z".to_string(), "whoami".to_string()),
+                ("a".to_string(), "logout".to_string())
+            ]
+        );
+
+        assert!(aliases(&json!({ "ALIASES": ["whoami"] })).is_err());
+        assert!(aliases(&json!({ "ALIASES": { "a": 1 } })).is_err());
+        assert!(aliases(&Value::Null).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::Error;
+
+/// How often a sleep checks whether it was cancelled.
+const SLEEP_GRANULARITY: Duration = Duration::from_millis(100);
+
+/// Shared flag telling long running work to stop at the next convenient
+/// point, either because it was cancelled or because its deadline passed.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancellationToken {
+    pub fn with_deadline(deadline: Option<Instant>) -> CancellationToken {
+        CancellationToken {
+            cancelled: Arc::default(),
+            deadline,
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Fails with a message saying why the work was stopped, if it was.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(Error::Cancelled("Cancelled".to_string()));
+        }
+        if self.is_cancelled() {
+            return Err(Error::Cancelled("Timed out".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Time left until the deadline, used to bound blocking requests.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Sleeps for `duration`, waking early to fail if cancelled meanwhile.
+    pub fn sleep(&self, duration: Duration) -> Result<(), Error> {
+        let end = Instant::now() + duration;
+        loop {
+            self.check()?;
+            let left = end.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(());
+            }
+            thread::sleep(left.min(SLEEP_GRANULARITY));
+        }
+    }
+}
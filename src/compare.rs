@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::dropbox::{api_path, DropboxClient, RemoteFile};
+use crate::error::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Difference {
+    Match,
+    Differs,
+    OnlyInFirst,
+    OnlyInSecond,
+}
+
+impl Difference {
+    fn marker(self) -> char {
+        match self {
+            Difference::Match => '=',
+            Difference::Differs => '~',
+            Difference::OnlyInFirst => '<',
+            Difference::OnlyInSecond => '>',
+        }
+    }
+}
+
+/// Keys the files by their path relative to `folder`. Keys are lowercased
+/// because Dropbox paths are case-insensitive, values keep the display case.
+fn by_relative_path<'a>(
+    files: &'a [RemoteFile],
+    folder: &str,
+) -> BTreeMap<String, (&'a str, &'a RemoteFile)> {
+    let prefix = api_path(folder).to_lowercase();
+    files
+        .iter()
+        .filter_map(|file| {
+            let relative = file.path_lower.strip_prefix(&prefix)?;
+            let display = file.path_display.get(prefix.len()..).unwrap_or(relative);
+            Some((relative.to_string(), (display, file)))
+        })
+        .collect()
+}
+
+fn compare<'a>(
+    first: &'a [RemoteFile],
+    first_folder: &str,
+    second: &'a [RemoteFile],
+    second_folder: &str,
+) -> Vec<(&'a str, Difference)> {
+    let first = by_relative_path(first, first_folder);
+    let second = by_relative_path(second, second_folder);
+    let paths: BTreeSet<&String> = first.keys().chain(second.keys()).collect();
+
+    paths
+        .into_iter()
+        .map(|path| match (first.get(path), second.get(path)) {
+            (Some((display, a)), Some((_, b))) if a.content_hash == b.content_hash => {
+                (*display, Difference::Match)
+            }
+            (Some((display, _)), Some(_)) => (*display, Difference::Differs),
+            (Some((display, _)), None) => (*display, Difference::OnlyInFirst),
+            (None, Some((display, _))) => (*display, Difference::OnlyInSecond),
+            (None, None) => unreachable!("path comes from one of the listings"),
+        })
+        .collect()
+}
+
+/// Lists both remote folders and prints how every file compares, followed by
+/// the number of files in each category.
+pub fn diff_remote(client: &DropboxClient, first: &str, second: &str) -> Result<(), Error> {
+    let first_files = client.list_folder(first)?;
+    let second_files = client.list_folder(second)?;
+    let differences = compare(&first_files, first, &second_files, second);
+
+    for (path, difference) in &differences {
+        say!("{} {path}", difference.marker());
+    }
+
+    let count = |kind| differences.iter().filter(|(_, d)| *d == kind).count();
+    say!(
+        "{} matching, {} differing, {} only in {first}, {} only in {second}",
+        count(Difference::Match),
+        count(Difference::Differs),
+        count(Difference::OnlyInFirst),
+        count(Difference::OnlyInSecond),
+    );
+
+    Ok(())
+}
+
+/// Lists the remote folder and fetches the metadata of every file again,
+/// printing the files whose revision or content hash changed in between.
+/// Fails if any file changed, so scripts can wait for a stable folder.
+pub fn verify_remote(client: &DropboxClient, folder: &str) -> Result<(), Error> {
+    let files = client.list_folder(folder)?;
+    let mut changed = 0;
+
+    for file in &files {
+        let discrepancy = match client.find_metadata(&file.path_lower)? {
+            Some(current) if current.rev != file.rev => {
+                format!("revision {} is now {}", file.rev, current.rev)
+            }
+            Some(current) if current.content_hash != file.content_hash => {
+                "content hash changed".to_string()
+            }
+            Some(_) => continue,
+            None => "no longer exists".to_string(),
+        };
+        say!("{}: {discrepancy}", file.path_display);
+        changed += 1;
+    }
+
+    say!("{} files checked, {changed} changed", files.len());
+    match changed {
+        0 => Ok(()),
+        _ => Err(Error::Verification(format!(
+            "{changed} files changed since they were listed"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, MockServer};
+
+    fn file(path_display: &str, content_hash: &str) -> RemoteFile {
+        RemoteFile {
+            id: format!("id:{path_display}"),
+            path_display: path_display.to_string(),
+            path_lower: path_display.to_lowercase(),
+            rev: "1".to_string(),
+            size: 1,
+            content_hash: content_hash.to_string(),
+        }
+    }
+
+    #[test]
+    fn compares_files_by_relative_path() {
+        let first = [
+            file("/A/same.txt", "1"),
+            file("/A/changed.txt", "2"),
+            file("/A/Docs/First.txt", "3"),
+        ];
+        let second = [
+            file("/b/same.txt", "1"),
+            file("/b/changed.txt", "9"),
+            file("/b/docs/second.txt", "4"),
+        ];
+
+        assert_eq!(
+            compare(&first, "/a", &second, "/B/"),
+            vec![
+                ("/changed.txt", Difference::Differs),
+                ("/Docs/First.txt", Difference::OnlyInFirst),
+                ("/docs/second.txt", Difference::OnlyInSecond),
+                ("/same.txt", Difference::Match),
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_paths_regardless_of_case() {
+        let first = [file("/a/Report.PDF", "1")];
+        let second = [file("/b/report.pdf", "1")];
+
+        assert_eq!(
+            compare(&first, "/a", &second, "/b"),
+            vec![("/Report.PDF", Difference::Match)]
+        );
+    }
+
+    #[test]
+    fn verify_reports_files_changed_since_the_listing() {
+        let listing = mock::listing(&[
+            mock::file("/a/same.txt", "1", "h1"),
+            mock::file("/a/changed.txt", "2", "h2"),
+            mock::file("/a/deleted.txt", "3", "h3"),
+        ]);
+        let same = mock::file("/a/same.txt", "1", "h1").to_string();
+        let changed = mock::file("/a/changed.txt", "4", "h4").to_string();
+        let not_found = r#"{"error_summary": "path/not_found/..", "error": {".tag": "path"}}"#;
+        let server = MockServer::start(vec![
+            (200, &listing),
+            (200, &same),
+            (200, &changed),
+            (409, not_found),
+        ]);
+        let client = mock::client(&server);
+
+        let error = verify_remote(&client, "/a").unwrap_err();
+        assert!(matches!(error, Error::Verification(_)), "{error}");
+        assert!(error.to_string().starts_with("2 files changed"), "{error}");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[2].path, "/files/get_metadata");
+        assert!(requests[2].body.contains("/a/changed.txt"));
+    }
+
+    #[test]
+    fn verify_stops_on_other_errors() {
+        let listing = mock::listing(&[
+            mock::file("/a/one.txt", "1", "h1"),
+            mock::file("/a/two.txt", "2", "h2"),
+        ]);
+        let server = MockServer::start(vec![(200, &listing), (400, "bad request")]);
+        let client = mock::client(&server);
+
+        let error = verify_remote(&client, "/a").unwrap_err();
+        assert!(matches!(error, Error::Other(_)), "{error}");
+        assert_eq!(server.requests().len(), 2);
+    }
+}
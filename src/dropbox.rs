@@ -0,0 +1,1057 @@
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+
+use crate::cancel::CancellationToken;
+use crate::error::Error;
+use crate::extract_value;
+
+const API_URL: &str = "https://api.dropboxapi.com/2";
+const CONTENT_URL: &str = "https://content.dropboxapi.com/2";
+const REVISIONS_LIMIT: u64 = 100;
+/// How many times a listing starts over after its cursor was reset before
+/// giving up, so a folder that keeps changing cannot loop forever.
+const MAX_LISTING_RESETS: u32 = 3;
+
+/// Timeout for API calls other than downloads, which may take arbitrarily long.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const JOB_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const UNAVAILABLE_FIRST_DELAY: Duration = Duration::from_secs(2);
+const UNAVAILABLE_MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+/// How long to keep retrying while Dropbox answers 503, long enough to ride
+/// out a typical maintenance window.
+const UNAVAILABLE_GIVE_UP: Duration = Duration::from_secs(20 * 60);
+const EMPTY_RESPONSE_ATTEMPTS: u32 = 3;
+const EMPTY_RESPONSE_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Size of the reads between cancellation checks while downloading.
+const DOWNLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Endpoints that only read from the account, the only ones allowed in
+/// read-only mode. Anything not listed here is refused, so endpoints added
+/// later stay blocked until they are known to be safe.
+const READ_ONLY_ENDPOINTS: &[&str] = &[
+    "files/download",
+    "files/get_metadata",
+    "files/list_folder",
+    "files/list_folder/continue",
+    "files/list_revisions",
+    "users/get_current_account",
+];
+
+pub struct RemoteFile {
+    pub id: String,
+    pub path_display: String,
+    pub path_lower: String,
+    pub rev: String,
+    pub size: u64,
+    pub content_hash: String,
+}
+
+pub struct Account {
+    pub account_id: String,
+    pub email: String,
+    pub display_name: String,
+    pub root_namespace_id: String,
+    pub account_type: String,
+}
+
+pub struct FileRevision {
+    pub rev: String,
+    pub size: u64,
+    pub server_modified: String,
+}
+
+impl FileRevision {
+    fn from_metadata(metadata: &Value) -> Option<FileRevision> {
+        Some(FileRevision {
+            rev: metadata.get("rev").and_then(extract_value)?,
+            size: metadata.get("size").and_then(Value::as_u64)?,
+            server_modified: metadata.get("server_modified").and_then(extract_value)?,
+        })
+    }
+}
+
+impl Account {
+    fn from_value(account: &Value) -> Option<Account> {
+        Some(Account {
+            account_id: account.get("account_id").and_then(extract_value)?,
+            email: account.get("email").and_then(extract_value)?,
+            display_name: account
+                .get("name")
+                .and_then(|name| name.get("display_name"))
+                .and_then(extract_value)?,
+            root_namespace_id: account
+                .get("root_info")
+                .and_then(|root_info| root_info.get("root_namespace_id"))
+                .and_then(extract_value)?,
+            account_type: account
+                .get("account_type")
+                .and_then(|account_type| account_type.get(".tag"))
+                .and_then(extract_value)?,
+        })
+    }
+}
+
+impl Account {
+    /// Number of days Dropbox keeps older revisions for this kind of account.
+    /// Plus and Professional both report `pro`, so the shorter window of the
+    /// two is assumed.
+    pub fn retention_days(&self) -> u64 {
+        match self.account_type.as_str() {
+            "business" => 180,
+            _ => 30,
+        }
+    }
+}
+
+impl RemoteFile {
+    fn from_metadata(metadata: &Value) -> Option<RemoteFile> {
+        Some(RemoteFile {
+            id: metadata.get("id").and_then(extract_value)?,
+            path_display: metadata.get("path_display").and_then(extract_value)?,
+            path_lower: metadata.get("path_lower").and_then(extract_value)?,
+            rev: metadata.get("rev").and_then(extract_value)?,
+            size: metadata.get("size").and_then(Value::as_u64)?,
+            content_hash: metadata.get("content_hash").and_then(extract_value)?,
+        })
+    }
+}
+
+/// Converts a user supplied folder path to the form the API expects, where
+/// the root is the empty string rather than `/`.
+pub fn api_path(path: &str) -> &str {
+    path.trim_end_matches('/')
+}
+
+/// Parses an API timestamp such as `2015-05-12T15:50:38Z` into seconds since
+/// the Unix epoch.
+pub fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let (date, time) = timestamp.strip_suffix('Z')?.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+
+    // Days since the epoch in the proleptic Gregorian calendar, counting
+    // years from March so the leap day comes last.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    u64::try_from(days * 86_400 + hour * 3_600 + minute * 60 + second).ok()
+}
+
+/// A number in `[0, 1)` that is random enough for failure injection, taken
+/// from the randomly keyed hasher std uses for `HashMap`.
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    bits as f64 / (1u64 << 53) as f64
+}
+
+fn failure(endpoint: &str, status: StatusCode, body: &str) -> Error {
+    match status {
+        StatusCode::UNAUTHORIZED => Error::Auth(format!("The access token was rejected: {body}")),
+        StatusCode::SERVICE_UNAVAILABLE => Error::Unavailable(format!(
+            "{endpoint} failed with {status}, Dropbox is temporarily unavailable: {body}"
+        )),
+        _ => Error::Other(format!("{endpoint} failed with {status}: {body}")),
+    }
+}
+
+/// Extracts the `error_summary` of an API error response, e.g.
+/// `path/not_found/..`.
+fn error_summary(body: &str) -> Option<String> {
+    serde_json::from_str::<Value>(body)
+        .ok()?
+        .get("error_summary")
+        .and_then(extract_value)
+}
+
+fn parse_json(body: &str) -> Result<Value, Error> {
+    match serde_json::from_str(body) {
+        Ok(parsed) => Ok(parsed),
+        Err(error) => Err(Error::Other(format!("Could not parse json: {error}"))),
+    }
+}
+
+/// Builds the HTTP client shared by all requests, sending `headers` on each
+/// of them. It has no overall timeout; callers set one per request.
+pub fn http_client(headers: &[(HeaderName, HeaderValue)]) -> Result<Client, String> {
+    let headers: HeaderMap = headers.iter().cloned().collect();
+    match Client::builder()
+        .default_headers(headers)
+        .timeout(None)
+        .build()
+    {
+        Ok(client) => Ok(client),
+        Err(error) => Err(format!("Could not create the HTTP client: {error}")),
+    }
+}
+
+/// Serializes `arg` for the `Dropbox-API-Arg` header, which only allows
+/// ASCII, by escaping everything else as JSON `\uXXXX` sequences.
+fn header_arg(arg: &Value) -> String {
+    let mut escaped = String::new();
+    for c in arg.to_string().chars() {
+        if c.is_ascii() {
+            escaped.push(c);
+        } else {
+            let mut units = [0; 2];
+            for unit in c.encode_utf16(&mut units) {
+                escaped.push_str(&format!("\\u{unit:04x}"));
+            }
+        }
+    }
+    escaped
+}
+
+/// Interprets a `files/save_url` style response: `None` while the job is
+/// still running, the saved file once it is complete.
+fn save_url_status(response: &Value) -> Result<Option<RemoteFile>, String> {
+    match response.get(".tag").and_then(extract_value).as_deref() {
+        Some("async_job_id") | Some("in_progress") => Ok(None),
+        Some("complete") => match RemoteFile::from_metadata(response) {
+            Some(file) => Ok(Some(file)),
+            None => Err("Could not get file metadata from the response".to_string()),
+        },
+        Some("failed") => Err(format!("Saving the URL failed: {}", response["failed"])),
+        _ => Err(format!("Unexpected save_url response: {response}")),
+    }
+}
+
+pub struct DropboxClient {
+    http: Client,
+    api_url: String,
+    content_url: String,
+    access_token: String,
+    read_only: bool,
+    path_root: Option<String>,
+    failure_rate: f64,
+    /// Source of the numbers compared with `failure_rate`.
+    fraction: Arc<dyn Fn() -> f64 + Send + Sync>,
+    skip_bad_entries: bool,
+    cancel: CancellationToken,
+}
+
+impl DropboxClient {
+    /// Creates a client authorized by `access_token`. A read-only client
+    /// refuses mutating endpoints before any request is sent.
+    pub fn new(http: Client, access_token: String, read_only: bool) -> DropboxClient {
+        DropboxClient {
+            http,
+            api_url: API_URL.to_string(),
+            content_url: CONTENT_URL.to_string(),
+            access_token,
+            read_only,
+            path_root: None,
+            failure_rate: 0.0,
+            fraction: Arc::new(random_fraction),
+            skip_bad_entries: false,
+            cancel: CancellationToken::default(),
+        }
+    }
+
+    /// Sends every request to `url` instead of the Dropbox API hosts.
+    #[cfg(test)]
+    pub fn with_base_url(self, url: &str) -> DropboxClient {
+        DropboxClient {
+            api_url: url.to_string(),
+            content_url: url.to_string(),
+            ..self
+        }
+    }
+
+    /// Makes requests and downloads stop once `cancel` is cancelled or its
+    /// deadline passes. Requests in flight are bounded by the deadline.
+    pub fn with_cancellation(self, cancel: CancellationToken) -> DropboxClient {
+        DropboxClient { cancel, ..self }
+    }
+
+    /// Makes listings drop entries that cannot be parsed, with a warning,
+    /// instead of failing as a whole.
+    pub fn with_skip_bad_entries(self, skip_bad_entries: bool) -> DropboxClient {
+        DropboxClient {
+            skip_bad_entries,
+            ..self
+        }
+    }
+
+    /// Makes the given fraction of API calls fail with a synthetic 503
+    /// instead of being sent, to exercise error handling in real runs.
+    pub fn with_simulated_failures(self, failure_rate: f64) -> DropboxClient {
+        DropboxClient {
+            failure_rate,
+            ..self
+        }
+    }
+
+    /// Decides which calls fail by the numbers `fraction` returns instead of
+    /// random ones, so tests can pick the failures.
+    #[cfg(test)]
+    pub fn with_fractions(
+        self,
+        fraction: impl Fn() -> f64 + Send + Sync + 'static,
+    ) -> DropboxClient {
+        DropboxClient {
+            fraction: Arc::new(fraction),
+            ..self
+        }
+    }
+
+    /// Makes paths resolve against the namespace `root_namespace_id` instead
+    /// of the user's home folder, which matters for team spaces.
+    pub fn with_path_root(self, root_namespace_id: String) -> DropboxClient {
+        DropboxClient {
+            path_root: Some(root_namespace_id),
+            ..self
+        }
+    }
+
+    /// Fails if the client was cancelled or `endpoint` is refused in read-only
+    /// mode, before anything is sent.
+    fn check_allowed(&self, endpoint: &str) -> Result<(), Error> {
+        self.cancel.check()?;
+
+        if self.read_only && !READ_ONLY_ENDPOINTS.contains(&endpoint) {
+            return Err(Error::ReadOnly(format!(
+                "Refusing to call {endpoint} in read-only mode"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Sends `request`, returning the response whatever its status. The
+    /// request gives up after `timeout`, or sooner if the deadline is closer.
+    fn send_unchecked(
+        &self,
+        endpoint: &str,
+        request: RequestBuilder,
+        timeout: Option<Duration>,
+    ) -> Result<Response, Error> {
+        self.check_allowed(endpoint)?;
+
+        let request = match &self.path_root {
+            Some(root) => request.header(
+                "Dropbox-API-Path-Root",
+                header_arg(&json!({ ".tag": "root", "root": root })),
+            ),
+            None => request,
+        };
+
+        let request = match (timeout, self.cancel.remaining()) {
+            (Some(timeout), Some(remaining)) => request.timeout(timeout.min(remaining)),
+            (Some(timeout), None) | (None, Some(timeout)) => request.timeout(timeout),
+            (None, None) => request,
+        };
+
+        match request.bearer_auth(&self.access_token).send() {
+            Ok(response) => Ok(response),
+            Err(error) => {
+                self.cancel.check()?;
+                Err(Error::Network(format!(
+                    "Could not get the response: {error}"
+                )))
+            }
+        }
+    }
+
+    /// Like `send_unchecked`, but while Dropbox answers 503 the request is
+    /// retried with exponential backoff for up to `UNAVAILABLE_GIVE_UP`,
+    /// printing one line per wait. Failures injected by
+    /// `with_simulated_failures` stand in for a 503 and are retried alike.
+    fn send_retrying(
+        &self,
+        endpoint: &str,
+        mut request: RequestBuilder,
+        timeout: Option<Duration>,
+    ) -> Result<Response, Error> {
+        let start = Instant::now();
+        let mut delay = UNAVAILABLE_FIRST_DELAY;
+
+        loop {
+            let retry = request.try_clone();
+            let response = if self.failure_rate > 0.0 && (self.fraction)() < self.failure_rate {
+                self.check_allowed(endpoint)?;
+                None
+            } else {
+                Some(self.send_unchecked(endpoint, request, timeout)?)
+            };
+
+            let unavailable = response
+                .as_ref()
+                .is_none_or(|response| response.status() == StatusCode::SERVICE_UNAVAILABLE);
+            let retry = match retry {
+                Some(retry) if unavailable && start.elapsed() + delay <= UNAVAILABLE_GIVE_UP => {
+                    retry
+                }
+                _ => {
+                    return response.ok_or_else(|| {
+                        failure(
+                            endpoint,
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "simulated failure",
+                        )
+                    })
+                }
+            };
+
+            eprintln!(
+                "Dropbox is temporarily unavailable, retrying {endpoint} in {} seconds",
+                delay.as_secs()
+            );
+            self.cancel.sleep(delay)?;
+            request = retry;
+            delay = (delay * 2).min(UNAVAILABLE_MAX_DELAY);
+        }
+    }
+
+    /// Calls the RPC `endpoint`, returning the status and body whatever the
+    /// status. A successful response with an empty body, which load balancers
+    /// occasionally produce, is retried a few times before giving up.
+    fn rpc_unchecked(&self, endpoint: &str, arg: &Value) -> Result<(StatusCode, String), Error> {
+        let mut attempt = 1;
+
+        loop {
+            let request = self
+                .http
+                .post(format!("{}/{endpoint}", self.api_url))
+                .json(arg);
+            let response = self.send_retrying(endpoint, request, Some(REQUEST_TIMEOUT))?;
+            let status = response.status();
+            let body = match response.text() {
+                Ok(body) => body,
+                Err(error) => {
+                    self.cancel.check()?;
+                    return Err(Error::Network(format!(
+                        "Could not read the response: {error}"
+                    )));
+                }
+            };
+
+            if !status.is_success() || !body.trim().is_empty() {
+                return Ok((status, body));
+            }
+            if attempt == EMPTY_RESPONSE_ATTEMPTS {
+                return Err(Error::Unavailable(format!(
+                    "{endpoint} kept returning empty responses"
+                )));
+            }
+
+            eprintln!("{endpoint} returned an empty response, retrying");
+            self.cancel.sleep(EMPTY_RESPONSE_RETRY_DELAY)?;
+            attempt += 1;
+        }
+    }
+
+    fn rpc(&self, endpoint: &str, arg: &Value) -> Result<Value, Error> {
+        match self.rpc_unchecked(endpoint, arg)? {
+            (status, body) if status.is_success() => parse_json(&body),
+            (status, body) => Err(failure(endpoint, status, &body)),
+        }
+    }
+
+    /// Fetches the next page of a listing, or `None` if the cursor is too old
+    /// and the listing has to start over.
+    fn list_folder_continue(&self, cursor: &str) -> Result<Option<Value>, Error> {
+        let endpoint = "files/list_folder/continue";
+        let (status, body) = self.rpc_unchecked(endpoint, &json!({ "cursor": cursor }))?;
+        if status.is_success() {
+            return parse_json(&body).map(Some);
+        }
+
+        if status == StatusCode::CONFLICT
+            && error_summary(&body).is_some_and(|summary| summary.starts_with("reset"))
+        {
+            return Ok(None);
+        }
+        Err(failure(endpoint, status, &body))
+    }
+
+    /// Lists every file below `path`, following the pagination cursor until the
+    /// listing is complete. Folders are not included.
+    pub fn list_folder(&self, path: &str) -> Result<Vec<RemoteFile>, Error> {
+        let mut response = self.rpc(
+            "files/list_folder",
+            &json!({ "path": api_path(path), "recursive": true }),
+        )?;
+        let mut files = Vec::new();
+        let mut skipped = 0;
+        let mut resets = 0;
+
+        loop {
+            let entries = match response.get("entries").and_then(Value::as_array) {
+                Some(entries) => entries,
+                None => {
+                    return Err(Error::Other(
+                        "Could not get entries from the listing".to_string(),
+                    ))
+                }
+            };
+
+            for entry in entries {
+                if entry.get(".tag").and_then(extract_value).as_deref() != Some("file") {
+                    continue;
+                }
+                match RemoteFile::from_metadata(entry) {
+                    Some(file) => files.push(file),
+                    None if self.skip_bad_entries => {
+                        eprintln!("Skipping malformed listing entry: {entry}");
+                        skipped += 1;
+                    }
+                    None => {
+                        return Err(Error::Other(format!(
+                            "Could not parse listing entry: {entry}"
+                        )))
+                    }
+                }
+            }
+
+            if response.get("has_more").and_then(Value::as_bool) != Some(true) {
+                if skipped > 0 {
+                    eprintln!("Skipped {skipped} malformed entries while listing {path}");
+                }
+                return Ok(files);
+            }
+
+            let cursor = match response.get("cursor").and_then(extract_value) {
+                Some(cursor) => cursor,
+                None => {
+                    return Err(Error::Other(
+                        "Could not get the cursor from the listing".to_string(),
+                    ))
+                }
+            };
+            response = match self.list_folder_continue(&cursor)? {
+                Some(response) => response,
+                None if resets == MAX_LISTING_RESETS => {
+                    return Err(Error::Other(format!(
+                        "The listing cursor for {path} expired {} times, giving up",
+                        resets + 1
+                    )))
+                }
+                None => {
+                    eprintln!("The listing cursor for {path} expired, listing it again");
+                    resets += 1;
+                    files.clear();
+                    skipped = 0;
+                    self.rpc(
+                        "files/list_folder",
+                        &json!({ "path": api_path(path), "recursive": true }),
+                    )?
+                }
+            };
+        }
+    }
+
+    /// Finds every file in the account whose Dropbox content hash equals
+    /// `content_hash`, listing the whole account from its root.
+    pub fn find_by_hash(&self, content_hash: &str) -> Result<Vec<RemoteFile>, Error> {
+        let mut files = self.list_folder("")?;
+        files.retain(|file| file.content_hash == content_hash);
+        Ok(files)
+    }
+
+    /// Fetches the current metadata of the file at `path`.
+    pub fn get_metadata(&self, path: &str) -> Result<RemoteFile, Error> {
+        match self.find_metadata(path)? {
+            Some(file) => Ok(file),
+            None => Err(Error::Other(format!("{path} does not exist"))),
+        }
+    }
+
+    /// Like `get_metadata`, but a missing file is `None` rather than an error.
+    pub fn find_metadata(&self, path: &str) -> Result<Option<RemoteFile>, Error> {
+        let endpoint = "files/get_metadata";
+        let (status, body) = self.rpc_unchecked(endpoint, &json!({ "path": path }))?;
+        if status == StatusCode::CONFLICT
+            && error_summary(&body).is_some_and(|summary| summary.starts_with("path/not_found"))
+        {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(failure(endpoint, status, &body));
+        }
+
+        let metadata = parse_json(&body)?;
+        match RemoteFile::from_metadata(&metadata) {
+            Some(file) => Ok(Some(file)),
+            None => Err(Error::Other(format!(
+                "Could not parse metadata: {metadata}"
+            ))),
+        }
+    }
+
+    /// Lists up to the last hundred revisions of the file at `path`, newest
+    /// first.
+    pub fn list_revisions(&self, path: &str) -> Result<Vec<FileRevision>, Error> {
+        self.revisions(path, REVISIONS_LIMIT)
+    }
+
+    /// Returns only the current revision of the file at `path`.
+    pub fn get_latest_file_version(&self, path: &str) -> Result<FileRevision, Error> {
+        match self.revisions(path, 1)?.into_iter().next() {
+            Some(revision) => Ok(revision),
+            None => Err(Error::Other(format!("{path} has no revisions"))),
+        }
+    }
+
+    fn revisions(&self, path: &str, limit: u64) -> Result<Vec<FileRevision>, Error> {
+        let response = self.rpc(
+            "files/list_revisions",
+            &json!({ "path": path, "mode": "path", "limit": limit }),
+        )?;
+
+        let entries = match response.get("entries").and_then(Value::as_array) {
+            Some(entries) => entries,
+            None => {
+                return Err(Error::Other(
+                    "Could not get entries from the revisions".to_string(),
+                ))
+            }
+        };
+
+        entries
+            .iter()
+            .map(|entry| match FileRevision::from_metadata(entry) {
+                Some(revision) => Ok(revision),
+                None => Err(Error::Other(format!("Could not parse revision: {entry}"))),
+            })
+            .collect()
+    }
+
+    /// Downloads the file at `path` into `destination`, returning the number
+    /// of bytes written. `path` may also be a `rev:<rev>` reference to an
+    /// older revision.
+    pub fn download(&self, path: &str, destination: &Path) -> Result<u64, Error> {
+        let request = self
+            .http
+            .post(format!("{}/files/download", self.content_url))
+            .header("Dropbox-API-Arg", header_arg(&json!({ "path": path })));
+        let mut response = self.send_retrying("files/download", request, None)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().unwrap_or_default();
+            let not_found =
+                error_summary(&body).is_some_and(|summary| summary.starts_with("path/not_found"));
+            if let (Some(rev), true) = (path.strip_prefix("rev:"), not_found) {
+                return Err(Error::RevisionPurged(format!(
+                    "Revision {rev} is no longer available, it may have been \
+                    purged after the account's retention window"
+                )));
+            }
+            return Err(failure("files/download", status, &body));
+        }
+
+        // Download next to the destination and move it into place only once
+        // complete, so a failed download leaves an existing file untouched.
+        let partial = match destination.file_name() {
+            Some(name) => {
+                destination.with_file_name(format!(".{}.rustyx-part", name.to_string_lossy()))
+            }
+            None => {
+                return Err(Error::Other(format!(
+                    "{} is not a file name",
+                    destination.display()
+                )))
+            }
+        };
+        let mut file = match File::create(&partial) {
+            Ok(file) => file,
+            Err(error) => {
+                return Err(Error::Other(format!(
+                    "Could not create {}: {error}",
+                    partial.display()
+                )))
+            }
+        };
+
+        let result = self
+            .copy_until_cancelled(&mut response, &mut file)
+            .and_then(|written| match fs::rename(&partial, destination) {
+                Ok(()) => Ok(written),
+                Err(error) => Err(Error::Other(format!(
+                    "Could not move it to {}: {error}",
+                    destination.display()
+                ))),
+            });
+        if result.is_err() {
+            drop(file);
+            let _ = fs::remove_file(&partial);
+        }
+        result.map_err(|error| match error {
+            Error::Other(error) => Error::Other(format!("Could not download {path}: {error}")),
+            error => error,
+        })
+    }
+
+    /// Copies `reader` into `writer` in chunks, checking for cancellation
+    /// between them.
+    fn copy_until_cancelled(
+        &self,
+        reader: &mut impl Read,
+        writer: &mut impl Write,
+    ) -> Result<u64, Error> {
+        let mut buffer = vec![0; DOWNLOAD_CHUNK_SIZE];
+        let mut written = 0;
+
+        loop {
+            self.cancel.check()?;
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => return Ok(written),
+                Ok(read) => read,
+                Err(error) => {
+                    self.cancel.check()?;
+                    return Err(Error::Other(error.to_string()));
+                }
+            };
+            if let Err(error) = writer.write_all(&buffer[..read]) {
+                return Err(Error::Other(error.to_string()));
+            }
+            written += read as u64;
+        }
+    }
+
+    /// Checks whether the access token is accepted, distinguishing a rejected
+    /// token (`Ok(false)`) from failures to reach the API.
+    pub fn verify_token(&self) -> Result<bool, Error> {
+        let endpoint = "users/get_current_account";
+        match self.rpc_unchecked(endpoint, &Value::Null)? {
+            (StatusCode::UNAUTHORIZED, _) => Ok(false),
+            (status, _) if status.is_success() => Ok(true),
+            (status, body) => Err(failure(endpoint, status, &body)),
+        }
+    }
+
+    pub fn get_current_account(&self) -> Result<Account, Error> {
+        let response = self.rpc("users/get_current_account", &Value::Null)?;
+        match Account::from_value(&response) {
+            Some(account) => Ok(account),
+            None => Err(Error::Other(
+                "Could not get account details from the response".to_string(),
+            )),
+        }
+    }
+
+    pub fn save_url_to_dropbox(&self, url: &str, remote_path: &str) -> Result<RemoteFile, Error> {
+        let response = self.rpc(
+            "files/save_url",
+            &json!({ "path": remote_path, "url": url }),
+        )?;
+
+        if let Some(file) = save_url_status(&response)? {
+            return Ok(file);
+        }
+
+        let job_id = match response.get("async_job_id").and_then(extract_value) {
+            Some(job_id) => job_id,
+            None => {
+                return Err(Error::Other(
+                    "Could not get the job id from the response".to_string(),
+                ))
+            }
+        };
+
+        loop {
+            self.cancel.sleep(JOB_POLL_INTERVAL)?;
+            let response = self.rpc(
+                "files/save_url/check_job_status",
+                &json!({ "async_job_id": job_id }),
+            )?;
+            if let Some(file) = save_url_status(&response)? {
+                return Ok(file);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::mock;
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_timestamp("2015-05-12T15:50:38Z"), Some(1_431_445_838));
+        assert_eq!(parse_timestamp("2024-02-29T12:00:00Z"), Some(1_709_208_000));
+        assert_eq!(parse_timestamp("2024-03-01T00:00:00Z"), Some(1_709_251_200));
+        assert_eq!(parse_timestamp("2015-05-12 15:50:38"), None);
+        assert_eq!(parse_timestamp("1969-12-31T23:59:59Z"), None);
+    }
+
+    #[test]
+    fn read_only_mode_refuses_writes_without_sending_them() {
+        let server = mock::MockServer::start(vec![(200, mock::ACCOUNT)]);
+        let client = DropboxClient::new(http_client(&[]).unwrap(), "token".to_string(), true)
+            .with_base_url(&server.url);
+
+        let error = match client.save_url_to_dropbox("https://example.com/a.txt", "/a.txt") {
+            Ok(_) => panic!("save_url was not refused"),
+            Err(error) => error,
+        };
+        assert!(matches!(error, Error::ReadOnly(_)), "{error}");
+        assert_eq!(error.exit_code(), 8);
+        assert!(server.requests().is_empty());
+
+        client.get_current_account().unwrap();
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn downloads_are_retried_while_dropbox_is_unavailable() {
+        let server = mock::MockServer::start(vec![(503, ""), (200, "contents")]);
+        let client = mock::client(&server);
+        let destination = mock::temp_path("unavailable.txt");
+
+        assert_eq!(client.download("/a.txt", &destination).unwrap(), 8);
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "contents");
+        fs::remove_file(&destination).unwrap();
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn simulated_failures_are_retried() {
+        let server = mock::MockServer::start(vec![(200, mock::ACCOUNT)]);
+        let fractions = Mutex::new(vec![0.1, 0.9].into_iter());
+        let client = mock::client(&server)
+            .with_simulated_failures(0.5)
+            .with_fractions(move || fractions.lock().unwrap().next().unwrap());
+
+        assert_eq!(client.get_current_account().unwrap().account_id, "dbid:1");
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn simulated_failures_are_not_sent() {
+        let server = mock::MockServer::start(vec![]);
+        let deadline = Instant::now() + Duration::from_millis(300);
+        let client = mock::client(&server)
+            .with_cancellation(CancellationToken::with_deadline(Some(deadline)))
+            .with_simulated_failures(1.0);
+
+        let error = client.get_current_account().err().unwrap();
+        assert!(matches!(error, Error::Cancelled(_)), "{error}");
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn verifies_access_tokens() {
+        let server = mock::MockServer::start(vec![
+            (200, mock::ACCOUNT),
+            (401, "invalid_access_token"),
+            (500, "internal error"),
+        ]);
+        let client = mock::client(&server).with_path_root("100".to_string());
+
+        assert!(client.verify_token().unwrap());
+        assert!(!client.verify_token().unwrap());
+        assert!(client.verify_token().is_err());
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].headers.contains_key("dropbox-api-path-root"));
+    }
+
+    #[test]
+    fn purged_revisions_have_their_own_error() {
+        let server = mock::MockServer::start(vec![(
+            409,
+            r#"{"error_summary": "path/not_found/..", "error": {".tag": "path"}}"#,
+        )]);
+        let client = mock::client(&server);
+        let destination = mock::temp_path("purged.txt");
+
+        let error = client
+            .download("rev:a1c10ce0dd78", &destination)
+            .unwrap_err();
+        assert!(matches!(error, Error::RevisionPurged(_)), "{error}");
+        assert_eq!(error.exit_code(), 9);
+        assert!(!destination.exists());
+    }
+
+    #[test]
+    fn skips_malformed_listing_entries_only_when_asked() {
+        let mut malformed = mock::file("/a/bad.txt", "2", "h2");
+        malformed["size"] = Value::from("large");
+        let listing = mock::listing(&[
+            mock::file("/a/one.txt", "1", "h1"),
+            malformed,
+            mock::file("/a/three.txt", "3", "h3"),
+        ]);
+        let server = mock::MockServer::start(vec![(200, &listing), (200, &listing)]);
+        let client = mock::client(&server);
+
+        assert!(client.list_folder("/a").is_err());
+
+        let files = client
+            .with_skip_bad_entries(true)
+            .list_folder("/a")
+            .unwrap();
+        let paths: Vec<&str> = files
+            .iter()
+            .map(|file| file.path_display.as_str())
+            .collect();
+        assert_eq!(paths, ["/a/one.txt", "/a/three.txt"]);
+    }
+
+    #[test]
+    fn lists_revisions_and_downloads_one() {
+        let revisions = r#"{"is_deleted": false, "entries": [
+            {"rev": "a2", "size": 8, "server_modified": "2024-03-20T10:00:00Z"},
+            {"rev": "a1", "size": 5, "server_modified": "2024-03-01T09:30:00Z"}
+        ]}"#;
+        let server = mock::MockServer::start(vec![(200, revisions), (200, "old")]);
+        let client = mock::client(&server);
+        let destination = mock::temp_path("revision.txt");
+
+        let revisions = client.list_revisions("/a.txt").unwrap();
+        let revs: Vec<&str> = revisions
+            .iter()
+            .map(|revision| revision.rev.as_str())
+            .collect();
+        assert_eq!(revs, ["a2", "a1"]);
+        assert_eq!(revisions[1].size, 5);
+        assert_eq!(revisions[1].server_modified, "2024-03-01T09:30:00Z");
+
+        assert_eq!(client.download("rev:a1", &destination).unwrap(), 3);
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "old");
+        fs::remove_file(&destination).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/files/list_revisions");
+        let arg: Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(arg["path"], "/a.txt");
+        assert_eq!(requests[1].path, "/files/download");
+        let arg: Value = serde_json::from_str(&requests[1].headers["dropbox-api-arg"]).unwrap();
+        assert_eq!(arg["path"], "rev:a1");
+    }
+
+    #[test]
+    fn gives_up_when_the_listing_keeps_resetting() {
+        let listing = json!({
+            "entries": [mock::file("/a/one.txt", "1", "h1")],
+            "cursor": "cursor",
+            "has_more": true,
+        })
+        .to_string();
+        let reset = r#"{"error_summary": "reset/..", "error": {".tag": "reset"}}"#;
+        let mut responses = Vec::new();
+        for _ in 0..4 {
+            responses.extend([(200, listing.as_str()), (409, reset)]);
+        }
+        let server = mock::MockServer::start(responses);
+        let client = mock::client(&server);
+
+        let error = client.list_folder("/a").err().unwrap();
+        assert!(error.to_string().contains("expired 4 times"), "{error}");
+        assert_eq!(server.requests().len(), 8);
+    }
+
+    #[test]
+    fn slow_downloads_stop_at_the_deadline_and_keep_the_existing_file() {
+        let server = mock::MockServer::start_slow(vec![(200, "new")], Duration::from_secs(5));
+        let deadline = Instant::now() + Duration::from_millis(300);
+        let client = mock::client(&server)
+            .with_cancellation(CancellationToken::with_deadline(Some(deadline)));
+        let destination = mock::temp_path("slow.txt");
+        let partial = destination.with_file_name(format!(
+            ".{}.rustyx-part",
+            destination.file_name().unwrap().to_string_lossy()
+        ));
+        fs::write(&destination, "existing").unwrap();
+
+        let start = Instant::now();
+        let error = client.download("/a.txt", &destination).unwrap_err();
+        assert!(matches!(error, Error::Cancelled(_)), "{error}");
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "existing");
+        assert!(!partial.exists());
+        fs::remove_file(&destination).unwrap();
+    }
+
+    #[test]
+    fn starts_the_listing_over_after_a_reset() {
+        let first_page = json!({
+            "entries": [mock::file("/a/one.txt", "1", "h1")],
+            "cursor": "cursor",
+            "has_more": true,
+        })
+        .to_string();
+        let reset = r#"{"error_summary": "reset/..", "error": {".tag": "reset"}}"#;
+        let complete = mock::listing(&[
+            mock::file("/a/one.txt", "1", "h1"),
+            mock::file("/a/two.txt", "2", "h2"),
+        ]);
+        let server =
+            mock::MockServer::start(vec![(200, &first_page), (409, reset), (200, &complete)]);
+        let client = mock::client(&server);
+
+        let files = client.list_folder("/a").unwrap();
+        let paths: Vec<&str> = files
+            .iter()
+            .map(|file| file.path_display.as_str())
+            .collect();
+        assert_eq!(paths, ["/a/one.txt", "/a/two.txt"]);
+
+        let requests: Vec<String> = server
+            .requests()
+            .into_iter()
+            .map(|request| request.path)
+            .collect();
+        assert_eq!(
+            requests,
+            [
+                "/files/list_folder",
+                "/files/list_folder/continue",
+                "/files/list_folder"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejected_tokens_are_authorization_errors() {
+        let server = mock::MockServer::start(vec![(401, "expired_access_token")]);
+        let client = mock::client(&server);
+
+        let error = client.list_folder("/a").err().unwrap();
+        assert!(matches!(error, Error::Auth(_)), "{error}");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].headers["authorization"], "Bearer token");
+    }
+
+    #[test]
+    fn empty_responses_are_retried() {
+        let server = mock::MockServer::start(vec![(200, ""), (200, mock::ACCOUNT)]);
+        let client = mock::client(&server);
+
+        assert_eq!(client.get_current_account().unwrap().account_id, "dbid:1");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn gives_up_on_repeated_empty_responses() {
+        let server = mock::MockServer::start(vec![(200, ""); EMPTY_RESPONSE_ATTEMPTS as usize]);
+        let client = mock::client(&server);
+
+        let error = client.get_current_account().err().unwrap();
+        assert!(matches!(error, Error::Unavailable(_)), "{error}");
+        assert_eq!(server.requests().len(), EMPTY_RESPONSE_ATTEMPTS as usize);
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+
+/// Failure of a command, categorized so scripts can tell apart failures that
+/// need a new login from those worth retrying. Each category has its own exit
+/// code unless `--exit-codes off` is given.
+#[derive(Debug)]
+pub enum Error {
+    /// Anything else, exit code 1.
+    Other(String),
+    /// The arguments or an alias could not be parsed. Exit code 2, the same
+    /// as clap's own usage errors.
+    Usage(String),
+    /// No valid credentials, or logging in failed. Exit code 3.
+    Auth(String),
+    /// The API could not be reached. Exit code 4.
+    Network(String),
+    /// Dropbox kept answering 503, e.g. during maintenance. Exit code 7.
+    Unavailable(String),
+    /// Remote files did not check out. Exit code 6.
+    Verification(String),
+    /// A call that could change the account was refused because of
+    /// `--read-only`. Exit code 8.
+    ReadOnly(String),
+    /// A requested revision no longer exists, e.g. purged after the
+    /// retention window. Exit code 9.
+    RevisionPurged(String),
+    /// Ctrl-C or `--timeout` stopped the work. Exit code 10.
+    Cancelled(String),
+}
+
+impl Error {
+    /// Exit code for the category. 5 is reserved for partial syncs.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Other(_) => 1,
+            Error::Usage(_) => 2,
+            Error::Auth(_) => 3,
+            Error::Network(_) => 4,
+            Error::Unavailable(_) => 7,
+            Error::Verification(_) => 6,
+            Error::ReadOnly(_) => 8,
+            Error::RevisionPurged(_) => 9,
+            Error::Cancelled(_) => 10,
+        }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Error {
+        Error::Other(message)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Other(message)
+            | Error::Usage(message)
+            | Error::Auth(message)
+            | Error::Network(message)
+            | Error::Unavailable(message)
+            | Error::Verification(message)
+            | Error::ReadOnly(message)
+            | Error::RevisionPurged(message)
+            | Error::Cancelled(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn categories_have_their_own_exit_codes() {
+        let message = String::new;
+        let codes = [
+            Error::Other(message()).exit_code(),
+            Error::Usage(message()).exit_code(),
+            Error::Auth(message()).exit_code(),
+            Error::Network(message()).exit_code(),
+            Error::Verification(message()).exit_code(),
+            Error::Unavailable(message()).exit_code(),
+            Error::ReadOnly(message()).exit_code(),
+            Error::RevisionPurged(message()).exit_code(),
+            Error::Cancelled(message()).exit_code(),
+        ];
+
+        assert_eq!(codes, [1, 2, 3, 4, 6, 7, 8, 9, 10]);
+        assert_eq!(Error::from("failed".to_string()).exit_code(), 1);
+    }
+}
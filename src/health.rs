@@ -0,0 +1,171 @@
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use reqwest::blocking::Client;
+
+use crate::cancel::CancellationToken;
+use crate::dropbox::DropboxClient;
+use crate::error::Error;
+use crate::{authorize_by_refresh_token, client_credentials, load_refresh_token};
+
+const API_HOST: &str = "api.dropbox.com";
+const HTTPS_PORT: u16 = 443;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const NETWORK_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const NETWORK_NOTICE_INTERVAL: Duration = Duration::from_secs(60);
+
+fn check<T>(name: &str, run: impl FnOnce() -> Result<T, String>) -> Option<T> {
+    let start = Instant::now();
+    let result = run();
+    let elapsed = start.elapsed().as_millis();
+
+    match result {
+        Ok(value) => {
+            say!("PASS {name} ({elapsed} ms)");
+            Some(value)
+        }
+        Err(error) => {
+            say!("FAIL {name} ({elapsed} ms): {error}");
+            None
+        }
+    }
+}
+
+fn resolve() -> Result<Vec<SocketAddr>, String> {
+    match (API_HOST, HTTPS_PORT).to_socket_addrs() {
+        Ok(addresses) => Ok(addresses.collect()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn connect(addresses: &[SocketAddr]) -> Result<(), String> {
+    let mut last_error = format!("{API_HOST} did not resolve to any address");
+    for address in addresses {
+        match TcpStream::connect_timeout(address, CONNECT_TIMEOUT) {
+            Ok(_) => return Ok(()),
+            Err(error) => last_error = format!("{address}: {error}"),
+        }
+    }
+    Err(last_error)
+}
+
+/// Sends a request to the API host through `http`, so with the same proxy
+/// settings as every other request. Any response at all means it is reachable.
+fn reach(http: &Client, cancel: &CancellationToken) -> Result<(), String> {
+    let timeout = match cancel.remaining() {
+        Some(remaining) => CONNECT_TIMEOUT.min(remaining),
+        None => CONNECT_TIMEOUT,
+    };
+    match http
+        .head(format!("https://{API_HOST}/"))
+        .timeout(timeout)
+        .send()
+    {
+        Ok(_) => Ok(()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+/// Blocks until the API host can be reached or `timeout` runs out, whichever
+/// comes first, and stops early when `cancel` trips. A zero timeout skips the
+/// probe: offline, the first API request then fails straight away with a
+/// network error, which saves a round trip on every run that is online.
+pub fn wait_for_network(
+    http: &Client,
+    timeout: Duration,
+    cancel: &CancellationToken,
+) -> Result<(), Error> {
+    if timeout.is_zero() {
+        return Ok(());
+    }
+
+    let start = Instant::now();
+    let mut last_notice: Option<Instant> = None;
+
+    loop {
+        cancel.check()?;
+        match reach(http, cancel) {
+            Ok(()) => return Ok(()),
+            Err(error) if start.elapsed() >= timeout => {
+                cancel.check()?;
+                return Err(Error::Network(format!(
+                    "Network still unavailable after {} seconds: {error}",
+                    timeout.as_secs()
+                )));
+            }
+            Err(_) => {}
+        }
+
+        if last_notice.is_none_or(|notice| notice.elapsed() >= NETWORK_NOTICE_INTERVAL) {
+            if !crate::quiet() {
+                eprintln!("Waiting for network...");
+            }
+            last_notice = Some(Instant::now());
+        }
+        cancel.sleep(NETWORK_RETRY_INTERVAL.min(timeout.saturating_sub(start.elapsed())))?;
+    }
+}
+
+fn exchange_token(use_keyring: bool, http: &Client) -> Result<String, String> {
+    let refresh_token = match load_refresh_token(use_keyring) {
+        Some(refresh_token) => refresh_token,
+        None => return Err("No cached refresh token".to_string()),
+    };
+    let (client_id, client_secret) = client_credentials(use_keyring)?;
+    let (access_token, _) =
+        authorize_by_refresh_token(http, &refresh_token, &client_id, &client_secret)?;
+    Ok(access_token)
+}
+
+/// Runs the connectivity and credential checks in order, failing if any of
+/// them did not pass.
+pub fn run_checks(use_keyring: bool, http: &Client) -> Result<(), String> {
+    let addresses = check("DNS resolution", resolve);
+    let connection = check("TCP connection", || match &addresses {
+        Some(addresses) => connect(addresses),
+        None => Err("Skipped, no address to connect to".to_string()),
+    });
+    let access_token = check("token exchange", || exchange_token(use_keyring, http));
+    let account = check("account lookup", || match &access_token {
+        Some(access_token) => DropboxClient::new(http.clone(), access_token.clone(), true)
+            .get_current_account()
+            .map_err(|error| error.to_string()),
+        None => Err("Skipped, no access token".to_string()),
+    });
+
+    match (addresses, connection, access_token, account) {
+        (Some(_), Some(_), Some(_), Some(_)) => Ok(()),
+        _ => Err("Some health checks failed".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+    use reqwest::Proxy;
+
+    #[test]
+    fn waits_through_the_proxy_until_the_timeout() {
+        let proxy = MockServer::start(vec![]);
+        let http = Client::builder()
+            .proxy(Proxy::all(&proxy.url).unwrap())
+            .build()
+            .unwrap();
+
+        let error = wait_for_network(&http, Duration::from_secs(1), &CancellationToken::default())
+            .unwrap_err();
+        assert!(matches!(error, Error::Network(_)), "{error}");
+        let requests = proxy.requests();
+        assert!(!requests.is_empty());
+        assert!(requests
+            .iter()
+            .all(|request| request.path == format!("{API_HOST}:{HTTPS_PORT}")));
+
+        let cancel = CancellationToken::default();
+        cancel.cancel();
+        let error = wait_for_network(&http, Duration::from_secs(60), &cancel).unwrap_err();
+        assert!(matches!(error, Error::Cancelled(_)), "{error}");
+        assert!(proxy.requests().is_empty());
+    }
+}
@@ -2,15 +2,62 @@ use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use clap::builder::BoolishValueParser;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use reqwest::blocking::Client;
+use reqwest::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::Url;
 use serde_json::{self, Value};
 
+use cancel::CancellationToken;
+use dropbox::{DropboxClient, FileRevision};
+use error::Error;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// Set while waiting for input, which Ctrl-C cannot interrupt cooperatively.
+static PROMPTING: AtomicBool = AtomicBool::new(false);
+
+/// Prints a line to stdout unless `--quiet` was given.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !$crate::quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+mod alias;
+mod cancel;
+mod compare;
+mod dropbox;
+mod error;
+mod health;
+#[cfg(test)]
+mod mock;
+mod secrets;
+mod transfers;
+
 const CLIENT_ID_FIELD: &str = "CLIENT_ID";
 const CLIENT_SECRET_FIELD: &str = "CLIENT_SECRET";
+const NETWORK_WAIT_TIMEOUT_FIELD: &str = "NETWORK_WAIT_TIMEOUT_SECS";
+const ALIASES_FIELD: &str = "ALIASES";
+const DEFAULT_COMMAND_FIELD: &str = "DEFAULT_COMMAND";
 const CACHE_NAME: &str = "rustyx";
+const ROOT_NAMESPACE_CACHE_NAME: &str = "root_namespace";
 const CONFIG_NAME: &str = "config.json";
+const ACCESS_TOKEN_VAR: &str = "RUSTYX_ACCESS_TOKEN";
+const AUTHORIZATION_CODE_PROMPT: &str = "Authorization code";
+
+fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
 
 fn extract_value(value: &Value) -> Option<String> {
     match value {
@@ -19,7 +66,7 @@ fn extract_value(value: &Value) -> Option<String> {
     }
 }
 
-fn cache_file() -> Result<PathBuf, String> {
+fn cache_dir() -> Result<PathBuf, String> {
     let home = match env::var("HOME") {
         Ok(home) => home,
         Err(error) => return Err(error.to_string()),
@@ -28,36 +75,118 @@ fn cache_file() -> Result<PathBuf, String> {
     let path = PathBuf::from(home).join(".cache").join(CACHE_NAME);
 
     match fs::create_dir_all(path.clone()) {
-        Ok(_) => Ok(path.join(CACHE_NAME)),
+        Ok(_) => Ok(path),
         Err(error) => Err(error.to_string()),
     }
 }
 
-fn load_refresh_token() -> Option<String> {
-    match cache_file() {
+fn cache_file(name: &str) -> Result<PathBuf, String> {
+    Ok(cache_dir()?.join(name))
+}
+
+fn load_refresh_token(use_keyring: bool) -> Option<String> {
+    if use_keyring {
+        if let Some(refresh_token) = secrets::load(secrets::REFRESH_TOKEN_ENTRY) {
+            return Some(refresh_token);
+        }
+    }
+
+    match cache_file(CACHE_NAME) {
         Ok(path) => fs::read_to_string(path).ok(),
         Err(_) => None,
     }
 }
 
-fn save_refresh_token(refresh_token: String) -> Result<(), String> {
-    match fs::write(cache_file()?, refresh_token) {
+fn load_root_namespace(cache: &Path) -> Option<String> {
+    fs::read_to_string(cache.join(ROOT_NAMESPACE_CACHE_NAME)).ok()
+}
+
+fn save_root_namespace(cache: &Path, root_namespace: &str) -> Result<(), String> {
+    match fs::write(cache.join(ROOT_NAMESPACE_CACHE_NAME), root_namespace) {
+        Ok(_) => Ok(()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+fn remove_cache_file(cache: &Path, name: &str) -> Result<(), String> {
+    match fs::remove_file(cache.join(name)) {
         Ok(_) => Ok(()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
         Err(error) => Err(error.to_string()),
     }
 }
 
-fn prompt(msg: &str) -> String {
-    print!("{}: ", msg);
-    io::stdout().flush().unwrap();
+/// Forgets the cached refresh token and everything derived from the account
+/// it belongs to.
+fn clear_cache(use_keyring: bool, cache: &Path) -> Result<(), String> {
+    if use_keyring {
+        secrets::delete(secrets::REFRESH_TOKEN_ENTRY)?;
+    }
+    remove_cache_file(cache, CACHE_NAME)?;
+    remove_cache_file(cache, ROOT_NAMESPACE_CACHE_NAME)
+}
+
+/// Saves the refresh token of a new login, forgetting the root namespace
+/// cached for the previous one, which may have been a different account.
+fn save_refresh_token(
+    refresh_token: String,
+    use_keyring: bool,
+    cache: &Path,
+) -> Result<(), String> {
+    if use_keyring {
+        secrets::store(secrets::REFRESH_TOKEN_ENTRY, &refresh_token)?;
+    } else if let Err(error) = fs::write(cache.join(CACHE_NAME), refresh_token) {
+        return Err(error.to_string());
+    }
+
+    remove_cache_file(cache, ROOT_NAMESPACE_CACHE_NAME)
+}
+
+fn ensure_interactive(msg: &str, options: &Options) -> Result<(), String> {
+    if options.non_interactive {
+        return Err(format!(
+            "Cannot prompt for \"{msg}\" in non-interactive mode"
+        ));
+    }
+    Ok(())
+}
+
+fn prompt(msg: &str, options: &Options) -> Result<String, String> {
+    ensure_interactive(msg, options)?;
+
+    PROMPTING.store(true, Ordering::Relaxed);
+    eprint!("{}: ", msg);
     let mut input = String::new();
-    io::stdin().read_line(&mut input).unwrap();
-    input.trim().to_owned()
+    let read = match io::stderr().flush() {
+        Ok(()) => io::stdin().read_line(&mut input),
+        Err(error) => Err(error),
+    };
+    PROMPTING.store(false, Ordering::Relaxed);
+    match read {
+        // End the prompt line so the error does not run on from it.
+        Ok(0) => eprintln!(),
+        Ok(_) => {}
+        Err(error) => return Err(format!("Could not read \"{msg}\": {error}")),
+    }
+
+    match input.trim() {
+        "" => Err(format!("No \"{msg}\" was entered")),
+        input => Ok(input.to_owned()),
+    }
 }
 
-fn tokens_from_params(params: &HashMap<&str, String>) -> Result<(String, Option<String>), String> {
-    let mut response = match Client::new()
+/// Wraps `value` in single quotes so a POSIX shell reads it back verbatim.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn tokens_from_params(
+    http: &Client,
+    params: &HashMap<&str, String>,
+) -> Result<(String, Option<String>), String> {
+    let mut response = match http
         .post("https://api.dropbox.com/oauth2/token")
+        .timeout(dropbox::REQUEST_TIMEOUT)
         .form(&params)
         .send()
         .and_then(|x| x.error_for_status())
@@ -76,8 +205,6 @@ fn tokens_from_params(params: &HashMap<&str, String>) -> Result<(String, Option<
         Err(error) => return Err(format!("Could not parse json: {error}")),
     };
 
-    println!("{parsed}");
-
     match (
         parsed.get("access_token").and_then(extract_value),
         parsed.get("refresh_token").and_then(extract_value),
@@ -87,85 +214,841 @@ fn tokens_from_params(params: &HashMap<&str, String>) -> Result<(String, Option<
     }
 }
 
+fn authorization_url(client_id: &str, options: &Options) -> Result<Url, String> {
+    let mut params = vec![
+        ("client_id", client_id),
+        ("token_access_type", options.token_access_type.as_str()),
+        ("response_type", "code"),
+    ];
+    if let Some(locale) = &options.locale {
+        params.push(("locale", locale));
+    }
+
+    match Url::parse_with_params("https://www.dropbox.com/oauth2/authorize", &params) {
+        Ok(url) => Ok(url),
+        Err(error) => Err(format!("Could not build the authorization URL: {error}")),
+    }
+}
+
 fn authorize_by_code(
+    http: &Client,
     client_id: &str,
     client_secret: &str,
+    options: &Options,
 ) -> Result<(String, Option<String>), String> {
-    let authorization_url = format!(
-        "https://www.dropbox.com/oauth2/authorize?\
-        client_id={client_id}&\
-        token_access_type=offline\
-        &response_type=code"
-    );
-
-    println!("{authorization_url}");
-    let auth_code = prompt("Authorization code");
+    ensure_interactive(AUTHORIZATION_CODE_PROMPT, options)?;
+    let authorization_url = authorization_url(client_id, options)?;
+
+    eprintln!("{authorization_url}");
+    let auth_code = prompt(AUTHORIZATION_CODE_PROMPT, options)?;
     let mut params = HashMap::new();
     params.insert("code", auth_code);
     params.insert("client_id", client_id.to_string());
     params.insert("client_secret", client_secret.to_string());
     params.insert("grant_type", "authorization_code".to_string());
-    tokens_from_params(&params)
+    tokens_from_params(http, &params)
 }
 
 fn authorize_by_refresh_token(
+    http: &Client,
     refresh_token: &str,
     client_id: &str,
     client_secret: &str,
 ) -> Result<(String, Option<String>), String> {
-    println!("Using the refresh token to authenticate...");
+    if !quiet() {
+        eprintln!("Using the refresh token to authenticate...");
+    }
     let mut params = HashMap::new();
     params.insert("refresh_token", refresh_token.to_string());
     params.insert("grant_type", "refresh_token".to_string());
     params.insert("client_id", client_id.to_string());
     params.insert("client_secret", client_secret.to_string());
-    tokens_from_params(&params)
+    tokens_from_params(http, &params)
+}
+
+/// Kind of token requested when logging in. Only `offline` returns a refresh
+/// token, with the others the login has to be repeated once the token expires.
+#[derive(Clone, Copy, ValueEnum)]
+enum TokenAccessType {
+    Offline,
+    Online,
+    Legacy,
+}
+
+impl TokenAccessType {
+    fn as_str(self) -> &'static str {
+        match self {
+            TokenAccessType::Offline => "offline",
+            TokenAccessType::Online => "online",
+            TokenAccessType::Legacy => "legacy",
+        }
+    }
+}
+
+/// Whether failures exit with a code per category or always with 1.
+#[derive(Clone, Copy, ValueEnum)]
+enum ExitCodes {
+    On,
+    Off,
 }
 
-fn main() {
-    let config = match fs::read_to_string(CONFIG_NAME)
+#[derive(Parser)]
+#[command(version, about = "Dropbox command line client")]
+struct Cli {
+    #[command(flatten)]
+    options: Options,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Args)]
+struct Options {
+    /// Read and store credentials in the OS keyring, falling back to the
+    /// configuration file and token cache
+    #[arg(long, global = true)]
+    use_keyring: bool,
+
+    /// Fail instead of prompting when input would be required
+    #[arg(
+        long,
+        global = true,
+        env = "RUSTYX_NONINTERACTIVE",
+        value_parser = BoolishValueParser::new()
+    )]
+    non_interactive: bool,
+
+    /// Read a short-lived access token from stdin instead of authorizing
+    #[arg(long, global = true)]
+    access_token_stdin: bool,
+
+    /// Refuse any API call that would modify the Dropbox account
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// Print nothing to stdout, leaving only errors on stderr
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Extra header sent with every request, may be repeated
+    #[arg(
+        long = "header",
+        value_name = "NAME: VALUE",
+        global = true,
+        value_parser = parse_header
+    )]
+    headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// Skip listing entries that cannot be parsed instead of failing
+    #[arg(long, global = true)]
+    skip_bad_entries: bool,
+
+    /// Fraction of API calls to fail with a synthetic 503, for testing
+    #[arg(
+        long,
+        global = true,
+        hide = true,
+        default_value_t = 0.0,
+        value_parser = parse_failure_rate
+    )]
+    simulate_failure: f64,
+
+    /// Give up on the command after this long, e.g. 90, 30s, 5m or 2h
+    #[arg(long, value_name = "DURATION", global = true, value_parser = parse_duration)]
+    timeout: Option<Duration>,
+
+    /// Language of the Dropbox consent screen shown when logging in, e.g. de
+    #[arg(long, global = true)]
+    locale: Option<String>,
+
+    /// Kind of token to request when logging in
+    #[arg(long, value_enum, global = true, default_value_t = TokenAccessType::Offline)]
+    token_access_type: TokenAccessType,
+
+    /// Exit with 2 for usage errors, 3 for authorization, 4 for network, 6 for
+    /// verification failures, 7 for Dropbox being unavailable, 8 for calls
+    /// refused in read-only mode, 9 for purged revisions and 10 when cancelled
+    /// or timed out instead of 1
+    #[arg(long, value_enum, global = true, default_value_t = ExitCodes::On)]
+    exit_codes: ExitCodes,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Save the file behind a URL directly into Dropbox
+    SaveUrl { url: String, remote_path: String },
+    /// Check API reachability and validity of the cached credentials
+    Health,
+    /// Store the client ID and secret in the OS keyring
+    StoreCredentials,
+    /// Compare the files of two remote folders by content hash
+    DiffRemote { first: String, second: String },
+    /// Check that the files of a remote folder do not change while listed
+    VerifyRemote { remote_path: String },
+    /// Find all files in the account with the given content hash
+    FindHash { content_hash: String },
+    /// List the revisions of a remote file
+    Revisions {
+        remote_path: String,
+        /// Show only the current revision
+        #[arg(long)]
+        latest: bool,
+    },
+    /// Print the ID and current revision of a remote file
+    Resolve { remote_path: String },
+    /// Download a remote file, optionally at an older revision
+    Download {
+        remote_path: String,
+        /// Local destination, defaults to the remote file name
+        local_path: Option<PathBuf>,
+        /// Revision to download instead of the current one
+        #[arg(long)]
+        rev: Option<String>,
+    },
+    /// Forget the cached refresh token and account details
+    Logout,
+    /// Inspect the command aliases from the configuration file
+    Alias {
+        #[command(subcommand)]
+        command: AliasCommand,
+    },
+    /// Show the account the credentials belong to
+    Whoami {
+        /// Print shell variable assignments suitable for eval
+        #[arg(long)]
+        shell_export: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum AliasCommand {
+    /// Show every alias and the command it expands to
+    List,
+}
+
+fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = match header.split_once(':') {
+        Some(header) => header,
+        None => return Err("expected a header in the form 'Name: Value'".to_string()),
+    };
+
+    let name = match HeaderName::from_bytes(name.trim().as_bytes()) {
+        Ok(name) => name,
+        Err(error) => return Err(format!("invalid header name: {error}")),
+    };
+    if name == AUTHORIZATION {
+        return Err("the Authorization header cannot be overridden".to_string());
+    }
+
+    match HeaderValue::from_str(value.trim()) {
+        Ok(value) => Ok((name, value)),
+        Err(error) => Err(format!("invalid header value: {error}")),
+    }
+}
+
+fn parse_failure_rate(rate: &str) -> Result<f64, String> {
+    match rate.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err("expected a number between 0 and 1".to_string()),
+    }
+}
+
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let (number, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => duration.split_at(index),
+        None => (duration, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return Err("expected a number of seconds, minutes or hours".to_string()),
+    };
+    match number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+    {
+        Some(seconds) => Ok(Duration::from_secs(seconds)),
+        None => Err("expected a number of seconds, minutes or hours".to_string()),
+    }
+}
+
+fn read_config() -> Result<Value, String> {
+    match fs::read_to_string(CONFIG_NAME)
         .map_err(|_| ())
         .and_then(|x| serde_json::from_str::<Value>(&x).map_err(|_| ()))
     {
-        Ok(config) => config,
-        Err(_) => {
-            eprintln!("Could not parse the configuration file");
-            return;
+        Ok(config) => Ok(config),
+        Err(_) => Err("Could not parse the configuration file".to_string()),
+    }
+}
+
+fn network_wait_timeout() -> Duration {
+    let seconds = read_config()
+        .ok()
+        .and_then(|config| {
+            config
+                .get(NETWORK_WAIT_TIMEOUT_FIELD)
+                .and_then(Value::as_u64)
+        })
+        .unwrap_or(0);
+    Duration::from_secs(seconds)
+}
+
+fn client_credentials(use_keyring: bool) -> Result<(String, String), String> {
+    if use_keyring {
+        if let (Some(client_id), Some(client_secret)) = (
+            secrets::load(secrets::CLIENT_ID_ENTRY),
+            secrets::load(secrets::CLIENT_SECRET_ENTRY),
+        ) {
+            return Ok((client_id, client_secret));
         }
-    };
+    }
 
-    let (client_id, client_secret) = match (
+    let config = read_config()?;
+
+    match (
         config.get(CLIENT_ID_FIELD).and_then(extract_value),
         config.get(CLIENT_SECRET_FIELD).and_then(extract_value),
     ) {
-        (Some(client_id), Some(client_secret)) => (client_id, client_secret),
-        _ => {
-            eprintln!("Could not retrieve configuration client ID or secret");
-            return;
+        (Some(client_id), Some(client_secret)) => Ok((client_id, client_secret)),
+        _ => Err("Could not retrieve configuration client ID or secret".to_string()),
+    }
+}
+
+/// Returns an access token handed to us from outside (stdin or the
+/// environment), which bypasses the OAuth flow and the cache entirely.
+fn external_access_token(options: &Options) -> Result<Option<String>, String> {
+    if options.access_token_stdin {
+        let mut access_token = String::new();
+        if let Err(error) = io::stdin().read_line(&mut access_token) {
+            return Err(format!("Could not read the access token: {error}"));
         }
-    };
+        return match access_token.trim() {
+            "" => Err("No access token on stdin".to_string()),
+            access_token => Ok(Some(access_token.to_string())),
+        };
+    }
+
+    Ok(env::var(ACCESS_TOKEN_VAR).ok())
+}
+
+fn access_token(options: &Options, http: &Client) -> Result<String, Error> {
+    match external_access_token(options).map_err(Error::Auth)? {
+        Some(access_token) => Ok(access_token),
+        None => authorize(options, http).map_err(Error::Auth),
+    }
+}
 
-    let result = match load_refresh_token() {
+fn authorize(options: &Options, http: &Client) -> Result<String, String> {
+    let (client_id, client_secret) = client_credentials(options.use_keyring)?;
+
+    let result = match load_refresh_token(options.use_keyring) {
         Some(refresh_token) => {
-            authorize_by_refresh_token(&refresh_token, &client_id, &client_secret)
+            authorize_by_refresh_token(http, &refresh_token, &client_id, &client_secret)
         }
-        None => authorize_by_code(&client_id, &client_secret),
+        None => authorize_by_code(http, &client_id, &client_secret, options),
     };
 
-    let (access_token, refresh_token) = match result {
-        Ok(tokens) => tokens,
+    let (access_token, refresh_token) = result?;
+
+    if let Some(refresh_token) = refresh_token {
+        save_refresh_token(refresh_token, options.use_keyring, &cache_dir()?)?;
+    }
+
+    Ok(access_token)
+}
+
+/// Creates a token that trips when `--timeout` runs out, and on the first
+/// Ctrl-C. A second Ctrl-C, or one at a prompt, exits straight away. The token is created once, so
+/// the deadline counts from the first call and later calls share it.
+fn cancellation(options: &Options) -> Result<CancellationToken, String> {
+    static CANCEL: OnceLock<CancellationToken> = OnceLock::new();
+    if let Some(cancel) = CANCEL.get() {
+        return Ok(cancel.clone());
+    }
+
+    let deadline = match options.timeout {
+        Some(timeout) => match Instant::now().checked_add(timeout) {
+            Some(deadline) => Some(deadline),
+            None => return Err("The timeout is too long".to_string()),
+        },
+        None => None,
+    };
+    let cancel = CancellationToken::with_deadline(deadline);
+    let handler = cancel.clone();
+    let installed = ctrlc::set_handler(move || {
+        if handler.is_cancelled() || PROMPTING.load(Ordering::Relaxed) {
+            process::exit(130);
+        }
+        eprintln!("Cancelling, press Ctrl-C again to exit immediately");
+        handler.cancel();
+    });
+
+    match installed {
+        Ok(()) => Ok(CANCEL.get_or_init(|| cancel).clone()),
+        Err(error) => Err(format!("Could not install the Ctrl-C handler: {error}")),
+    }
+}
+
+/// Whether each of `revisions`, newest first, is past the retention window.
+/// Dropbox keeps an older revision for `retention` seconds after the next one
+/// replaced it, so the newest revision never expires.
+fn outside_retention(revisions: &[FileRevision], retention: u64, now: u64) -> Vec<bool> {
+    let replaced = revisions.windows(2).map(|pair| {
+        dropbox::parse_timestamp(&pair[0].server_modified)
+            .is_some_and(|replaced| replaced + retention < now)
+    });
+    iter::once(false)
+        .chain(replaced)
+        .take(revisions.len())
+        .collect()
+}
+
+/// Creates an API client. With cached credentials, requests are made
+/// relative to the account's root namespace, looked up once after login and
+/// cached so later runs skip the extra `users/get_current_account` call.
+fn dropbox_client(options: &Options, http: &Client) -> Result<DropboxClient, Error> {
+    connect(options, http, false)
+}
+
+/// Like `dropbox_client`, but an access token given from outside is checked
+/// before it is used, so long operations fail up front on a bad token.
+fn verified_dropbox_client(options: &Options, http: &Client) -> Result<DropboxClient, Error> {
+    connect(options, http, true)
+}
+
+fn connect(options: &Options, http: &Client, verify: bool) -> Result<DropboxClient, Error> {
+    let new_client = |access_token| -> Result<DropboxClient, String> {
+        Ok(
+            DropboxClient::new(http.clone(), access_token, options.read_only)
+                .with_simulated_failures(options.simulate_failure)
+                .with_skip_bad_entries(options.skip_bad_entries)
+                .with_cancellation(cancellation(options)?),
+        )
+    };
+
+    if let Some(access_token) = external_access_token(options).map_err(Error::Auth)? {
+        let client = new_client(access_token)?;
+        if verify && !client.verify_token()? {
+            return Err(Error::Auth("The access token is not valid".to_string()));
+        }
+        return Ok(client);
+    }
+
+    // The access token was just issued by Dropbox, so it needs no checking.
+    let client = new_client(authorize(options, http).map_err(Error::Auth)?)?;
+    let root_namespace = root_namespace(&client, &cache_dir()?)?;
+    Ok(client.with_path_root(root_namespace))
+}
+
+/// The root namespace of the logged in account, from the cache or else looked
+/// up and cached.
+fn root_namespace(client: &DropboxClient, cache: &Path) -> Result<String, Error> {
+    if let Some(root_namespace) = load_root_namespace(cache) {
+        return Ok(root_namespace);
+    }
+
+    let root_namespace = client.get_current_account()?.root_namespace_id;
+    save_root_namespace(cache, &root_namespace)?;
+    Ok(root_namespace)
+}
+
+fn run(cli: Cli) -> Result<(), Error> {
+    let options = &cli.options;
+    QUIET.store(options.quiet, Ordering::Relaxed);
+    let http = &dropbox::http_client(&options.headers)?;
+
+    if !matches!(
+        cli.command,
+        Some(Command::Health | Command::StoreCredentials | Command::Logout | Command::Alias { .. })
+    ) {
+        health::wait_for_network(http, network_wait_timeout(), &cancellation(options)?)?;
+    }
+
+    match cli.command {
+        None => {
+            let access_token = access_token(options, http)?;
+            say!("Access token {access_token}");
+        }
+        Some(Command::SaveUrl { url, remote_path }) => {
+            let file = dropbox_client(options, http)?.save_url_to_dropbox(&url, &remote_path)?;
+            say!("Saved {} ({} bytes)", file.path_display, file.size);
+        }
+        Some(Command::Health) => health::run_checks(options.use_keyring, http)?,
+        Some(Command::StoreCredentials) => {
+            let client_id = prompt("Client ID", options)?;
+            let client_secret = prompt("Client secret", options)?;
+            secrets::store(secrets::CLIENT_ID_ENTRY, &client_id)?;
+            secrets::store(secrets::CLIENT_SECRET_ENTRY, &client_secret)?;
+            say!("Credentials stored in the keyring");
+        }
+        Some(Command::DiffRemote { first, second }) => {
+            compare::diff_remote(&verified_dropbox_client(options, http)?, &first, &second)?
+        }
+        Some(Command::VerifyRemote { remote_path }) => {
+            compare::verify_remote(&verified_dropbox_client(options, http)?, &remote_path)?
+        }
+        Some(Command::FindHash { content_hash }) => {
+            for file in verified_dropbox_client(options, http)?.find_by_hash(&content_hash)? {
+                say!("{}", file.path_display);
+            }
+        }
+        Some(Command::Revisions {
+            remote_path,
+            latest,
+        }) => {
+            let client = dropbox_client(options, http)?;
+            let revisions = if latest {
+                vec![client.get_latest_file_version(&remote_path)?]
+            } else {
+                client.list_revisions(&remote_path)?
+            };
+            // The current revision never expires, so a lone one needs no
+            // account lookup.
+            let expired = match revisions.len() {
+                0 | 1 => vec![false; revisions.len()],
+                _ => {
+                    let retention = client.get_current_account()?.retention_days() * 24 * 60 * 60;
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |now| now.as_secs());
+                    outside_retention(&revisions, retention, now)
+                }
+            };
+
+            for (revision, expired) in revisions.iter().zip(expired) {
+                say!(
+                    "{} {:>12} {}{}",
+                    revision.rev,
+                    revision.size,
+                    revision.server_modified,
+                    if expired {
+                        " (outside the retention window)"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        Some(Command::Resolve { remote_path }) => {
+            let file = dropbox_client(options, http)?.get_metadata(&remote_path)?;
+            say!("{} {}", file.id, file.rev);
+        }
+        Some(Command::Download {
+            remote_path,
+            local_path,
+            rev,
+        }) => {
+            let local_path = match local_path
+                .or_else(|| PathBuf::from(&remote_path).file_name().map(PathBuf::from))
+            {
+                Some(local_path) => local_path,
+                None => {
+                    return Err(Error::Other(format!(
+                        "Could not get a file name from {remote_path}"
+                    )))
+                }
+            };
+            let path = match &rev {
+                Some(rev) => format!("rev:{rev}"),
+                None => remote_path.clone(),
+            };
+            let client = dropbox_client(options, http)?;
+            let start = Instant::now();
+            let result = client.download(&path, &local_path);
+            let event = transfers::TransferEvent::Download {
+                path: remote_path.clone(),
+                rev: rev.clone(),
+                bytes: *result.as_ref().unwrap_or(&0),
+                duration_ms: start.elapsed().as_millis(),
+                status: match &result {
+                    Ok(_) => "ok".to_string(),
+                    Err(error) => error.to_string(),
+                },
+            };
+            if let Err(error) = transfers::log_transfer(event) {
+                eprintln!("{error}");
+            }
+
+            let bytes = result?;
+            say!(
+                "Downloaded {remote_path} to {} ({bytes} bytes)",
+                local_path.display()
+            );
+        }
+        Some(Command::Alias {
+            command: AliasCommand::List,
+        }) => {
+            let config = read_config().unwrap_or(Value::Null);
+            for (name, expansion) in alias::aliases(&config)? {
+                say!("{name} = {expansion}");
+            }
+            if let Some(default) = alias::default_command(&config) {
+                say!("Default command: {default}");
+            }
+        }
+        Some(Command::Logout) => {
+            clear_cache(options.use_keyring, &cache_dir()?)?;
+            say!("Logged out");
+        }
+        Some(Command::Whoami { shell_export }) => {
+            let account = dropbox_client(options, http)?.get_current_account()?;
+            if shell_export {
+                say!("RUSTYX_ACCOUNT_ID={}", shell_quote(&account.account_id));
+                say!("RUSTYX_ACCOUNT_EMAIL={}", shell_quote(&account.email));
+                say!("RUSTYX_ACCOUNT_NAME={}", shell_quote(&account.display_name));
+            } else {
+                say!("{} <{}>", account.display_name, account.email);
+                say!("Account ID {}", account.account_id);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The `--exit-codes` setting, found without clap so that it also applies to
+/// errors in the arguments themselves. The last occurrence wins.
+fn exit_codes_before_parsing(args: &[String]) -> ExitCodes {
+    let mut exit_codes = ExitCodes::On;
+    let mut args = args.iter().take_while(|arg| *arg != "--");
+    while let Some(arg) = args.next() {
+        let value = match arg.strip_prefix("--exit-codes") {
+            Some("") => args.next().map(String::as_str),
+            Some(value) => value.strip_prefix('='),
+            None => continue,
+        };
+        if let Some(value) = value.and_then(|value| ExitCodes::from_str(value, false).ok()) {
+            exit_codes = value;
+        }
+    }
+    exit_codes
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    let mut exit_codes = exit_codes_before_parsing(&args);
+    let exit_code = |error: &Error, exit_codes| match exit_codes {
+        ExitCodes::On => ExitCode::from(error.exit_code()),
+        ExitCodes::Off => ExitCode::FAILURE,
+    };
+
+    // Without a configuration file there is nothing to expand.
+    let config = read_config().unwrap_or(Value::Null);
+    let args = match alias::expand(args, &Cli::command(), &config) {
+        Ok(args) => args,
         Err(error) => {
+            let error = Error::Usage(error);
             eprintln!("{error}");
-            return;
+            return exit_code(&error, exit_codes);
+        }
+    };
+    let cli = match Cli::try_parse_from(args) {
+        Ok(cli) => cli,
+        Err(error) => {
+            let _ = error.print();
+            return match error.exit_code() {
+                0 => ExitCode::SUCCESS,
+                _ => exit_code(&Error::Usage(error.to_string()), exit_codes),
+            };
         }
     };
 
-    if let Some(refresh_token) = refresh_token {
-        if let Err(error) = save_refresh_token(refresh_token) {
+    exit_codes = cli.options.exit_codes;
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
             eprintln!("{error}");
-            return;
+            exit_code(&error, exit_codes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert!(parse_duration("5x").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("99999999999999999h").is_err());
+    }
+
+    #[test]
+    fn authorization_url_has_locale_and_access_type() {
+        let options = Cli::parse_from([
+            "rustyx",
+            "--locale",
+            "pt-BR",
+            "--token-access-type",
+            "online",
+        ])
+        .options;
+        let url = authorization_url("client id", &options).unwrap();
+        let query: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+
+        assert_eq!(url.host_str(), Some("www.dropbox.com"));
+        assert!(query.contains(&("client_id".to_string(), "client id".to_string())));
+        assert!(query.contains(&("token_access_type".to_string(), "online".to_string())));
+        assert!(query.contains(&("locale".to_string(), "pt-BR".to_string())));
+
+        let options = Cli::parse_from(["rustyx"]).options;
+        let url = authorization_url("id", &options).unwrap();
+        assert!(url
+            .query_pairs()
+            .any(|(k, v)| k == "token_access_type" && v == "offline"));
+        assert!(!url.query_pairs().any(|(k, _)| k == "locale"));
+        assert!(Cli::try_parse_from(["rustyx", "--token-access-type", "forever"]).is_err());
+    }
+
+    #[test]
+    fn non_interactive_mode_refuses_to_prompt() {
+        let options = Cli::parse_from(["rustyx", "--non-interactive"]).options;
+        let http = dropbox::http_client(&[]).unwrap();
+
+        assert!(prompt("Client ID", &options).is_err());
+        let error = authorize_by_code(&http, "id", "secret", &options).unwrap_err();
+        assert!(error.contains("non-interactive"), "{error}");
+    }
+
+    #[test]
+    fn shell_quotes_special_characters() {
+        assert_eq!(shell_quote("Jane Doe"), "'Jane Doe'");
+        assert_eq!(shell_quote("O'Brien"), "'O'\\''Brien'");
+        assert_eq!(
+            shell_quote("$(rm -rf ~) `x` \"y\" \\"),
+            "'$(rm -rf ~) `x` \"y\" \\'"
+        );
+        assert_eq!(shell_quote(""), "''");
+
+        let name = "O'Brien $HOME `id` \"quoted\" \\ ; newline\nend";
+        let output = process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("printf %s {}", shell_quote(name)))
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), name);
+    }
+
+    #[test]
+    fn parses_headers() {
+        let (name, value) = parse_header("X-Trace-Id:  abc 123 ").unwrap();
+        assert_eq!(name, "x-trace-id");
+        assert_eq!(value, "abc 123");
+
+        assert!(parse_header("X-Trace-Id abc").is_err());
+        assert!(parse_header("Bad Name: value").is_err());
+        assert!(parse_header("X-Trace-Id: bad\nvalue").is_err());
+        for header in ["Authorization: x", "AUTHORIZATION: x", "authorization: x"] {
+            let error = parse_header(header).unwrap_err();
+            assert!(error.contains("Authorization"), "{error}");
+        }
+    }
+
+    #[test]
+    fn sends_custom_headers() {
+        let server = mock::MockServer::start(vec![(200, mock::ACCOUNT)]);
+        let header = parse_header("X-Trace-Id: abc").unwrap();
+        let http = dropbox::http_client(&[header]).unwrap();
+        let client =
+            DropboxClient::new(http, "token".to_string(), false).with_base_url(&server.url);
+
+        client.get_current_account().unwrap();
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/users/get_current_account");
+        assert_eq!(requests[0].body, "null");
+        assert_eq!(requests[0].headers["x-trace-id"], "abc");
+        assert_eq!(requests[0].headers["authorization"], "Bearer token");
+    }
+
+    #[test]
+    fn only_replaced_revisions_expire() {
+        let revision = |server_modified: &str| FileRevision {
+            rev: "1".to_string(),
+            size: 1,
+            server_modified: server_modified.to_string(),
         };
+        let day = 24 * 60 * 60;
+        let now = dropbox::parse_timestamp("2024-03-31T00:00:00Z").unwrap();
+        let revisions = [
+            revision("2024-03-25T00:00:00Z"),
+            revision("2024-03-20T00:00:00Z"),
+            revision("2024-01-01T00:00:00Z"),
+            revision("2023-01-01T00:00:00Z"),
+        ];
+
+        assert_eq!(
+            outside_retention(&revisions, 30 * day, now),
+            [false, false, false, true]
+        );
+        assert_eq!(
+            outside_retention(&revisions, 180 * day, now),
+            [false, false, false, false]
+        );
+        assert!(outside_retention(&[], 30 * day, now).is_empty());
     }
 
-    println!("Access token {access_token}");
+    #[test]
+    fn finds_exit_codes_before_parsing() {
+        let args =
+            |args: &[&str]| -> Vec<String> { args.iter().map(|arg| arg.to_string()).collect() };
+
+        assert!(matches!(
+            exit_codes_before_parsing(&args(&["rustyx", "ls"])),
+            ExitCodes::On
+        ));
+        assert!(matches!(
+            exit_codes_before_parsing(&args(&["rustyx", "--exit-codes", "off", "ls"])),
+            ExitCodes::Off
+        ));
+        assert!(matches!(
+            exit_codes_before_parsing(&args(&["rustyx", "ls", "--exit-codes=off"])),
+            ExitCodes::Off
+        ));
+        assert!(matches!(
+            exit_codes_before_parsing(&args(&["rustyx", "--exit-codes=off", "--exit-codes", "on"])),
+            ExitCodes::On
+        ));
+        assert!(matches!(
+            exit_codes_before_parsing(&args(&["rustyx", "ls", "--", "--exit-codes=off"])),
+            ExitCodes::On
+        ));
+    }
+
+    #[test]
+    fn caches_the_root_namespace_until_the_next_login() {
+        let cache = mock::temp_path("cache");
+        fs::create_dir_all(&cache).unwrap();
+        let server = mock::MockServer::start(vec![(200, mock::ACCOUNT), (200, mock::ACCOUNT)]);
+        let client = mock::client(&server);
+        let cached = cache.join(ROOT_NAMESPACE_CACHE_NAME);
+
+        assert_eq!(root_namespace(&client, &cache).unwrap(), "100");
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(fs::read_to_string(&cached).unwrap(), "100");
+        assert_eq!(root_namespace(&client, &cache).unwrap(), "100");
+        assert!(server.requests().is_empty());
+
+        save_refresh_token("refresh".to_string(), false, &cache).unwrap();
+        assert!(!cached.exists());
+        assert_eq!(
+            fs::read_to_string(cache.join(CACHE_NAME)).unwrap(),
+            "refresh"
+        );
+        assert_eq!(root_namespace(&client, &cache).unwrap(), "100");
+        assert_eq!(server.requests().len(), 1);
+
+        clear_cache(false, &cache).unwrap();
+        assert!(!cached.exists());
+        assert!(!cache.join(CACHE_NAME).exists());
+        fs::remove_dir_all(&cache).unwrap();
+    }
 }
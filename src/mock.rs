@@ -0,0 +1,138 @@
+//! Minimal HTTP server standing in for the Dropbox API in tests.
+
+use std::collections::HashMap;
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::dropbox::{http_client, DropboxClient};
+
+pub struct Request {
+    pub path: String,
+    /// Header names are lowercased.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    /// Answers requests with `responses` in order, one `(status, body)` pair
+    /// each, and with 500 once they run out.
+    pub fn start(responses: Vec<(u16, &str)>) -> MockServer {
+        MockServer::start_slow(responses, Duration::ZERO)
+    }
+
+    /// Like `start`, but waits `delay` between sending the headers and the
+    /// body of every response.
+    pub fn start_slow(responses: Vec<(u16, &str)>, delay: Duration) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let mut responses: Vec<(u16, String)> = responses
+            .into_iter()
+            .rev()
+            .map(|(status, body)| (status, body.to_string()))
+            .collect();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let path = line.split_whitespace().nth(1).unwrap_or("").to_string();
+
+                let mut headers = HashMap::new();
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(':') {
+                        Some((name, value)) => {
+                            headers.insert(name.to_lowercase(), value.trim().to_string())
+                        }
+                        None => break,
+                    };
+                }
+
+                let length = headers
+                    .get("content-length")
+                    .and_then(|length| length.parse().ok())
+                    .unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                recorded.lock().unwrap().push(Request {
+                    path,
+                    headers,
+                    body: String::from_utf8_lossy(&body).into_owned(),
+                });
+
+                let (status, body) = responses.pop().unwrap_or((500, String::new()));
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status} Mock\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = stream.flush();
+                thread::sleep(delay);
+                let _ = stream.write_all(body.as_bytes());
+            }
+        });
+
+        MockServer { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        std::mem::take(&mut *self.requests.lock().unwrap())
+    }
+}
+
+/// A client with a made up token that sends everything to `server`.
+pub fn client(server: &MockServer) -> DropboxClient {
+    DropboxClient::new(http_client(&[]).unwrap(), "token".to_string(), false)
+        .with_base_url(&server.url)
+}
+
+/// Response body of `users/get_current_account` for a basic account.
+pub const ACCOUNT: &str = r#"{
+    "account_id": "dbid:1",
+    "email": "jane@example.com",
+    "name": {"display_name": "Jane Doe"},
+    "root_info": {"root_namespace_id": "100"},
+    "account_type": {".tag": "basic"}
+}"#;
+
+/// Path in the system temporary directory unique to this test process.
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("rustyx-{}-{name}", process::id()))
+}
+
+/// Metadata of a file as Dropbox returns it in listings.
+pub fn file(path: &str, rev: &str, content_hash: &str) -> Value {
+    json!({
+        ".tag": "file",
+        "id": format!("id:{path}"),
+        "path_display": path,
+        "path_lower": path.to_lowercase(),
+        "rev": rev,
+        "size": 1,
+        "content_hash": content_hash,
+    })
+}
+
+/// Response body of a complete `files/list_folder` call.
+pub fn listing(entries: &[Value]) -> String {
+    json!({ "entries": entries, "cursor": "cursor", "has_more": false }).to_string()
+}
@@ -0,0 +1,118 @@
+use keyring::Entry;
+
+const SERVICE: &str = "rustyx";
+
+pub const CLIENT_ID_ENTRY: &str = "client_id";
+pub const CLIENT_SECRET_ENTRY: &str = "client_secret";
+pub const REFRESH_TOKEN_ENTRY: &str = "refresh_token";
+
+fn entry(name: &str) -> Result<Entry, String> {
+    match Entry::new(SERVICE, name) {
+        Ok(entry) => Ok(entry),
+        Err(error) => Err(format!("Could not open keyring entry {name}: {error}")),
+    }
+}
+
+/// Reads a value from the OS keyring, treating a missing entry or an
+/// unavailable keyring alike so callers can fall back to the files.
+pub fn load(name: &str) -> Option<String> {
+    entry(name).ok()?.get_password().ok()
+}
+
+pub fn store(name: &str, value: &str) -> Result<(), String> {
+    match entry(name)?.set_password(value) {
+        Ok(_) => Ok(()),
+        Err(error) => Err(format!("Could not store {name} in the keyring: {error}")),
+    }
+}
+
+/// Removes a value from the OS keyring, succeeding if it was never there.
+pub fn delete(name: &str) -> Result<(), String> {
+    match entry(name)?.delete_credential() {
+        Ok(_) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(error) => Err(format!("Could not remove {name} from the keyring: {error}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use keyring::credential::{Credential, CredentialApi, CredentialBuilderApi};
+
+    use super::*;
+
+    type Store = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// In-memory keyring shared by every entry with the same service and
+    /// user, unlike `keyring::mock` whose entries each hold their own value.
+    struct MemoryBuilder(Store);
+
+    struct MemoryCredential {
+        store: Store,
+        key: String,
+    }
+
+    impl CredentialBuilderApi for MemoryBuilder {
+        fn build(
+            &self,
+            _: Option<&str>,
+            service: &str,
+            user: &str,
+        ) -> keyring::Result<Box<Credential>> {
+            Ok(Box::new(MemoryCredential {
+                store: self.0.clone(),
+                key: format!("{service}/{user}"),
+            }))
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    impl CredentialApi for MemoryCredential {
+        fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+            self.store
+                .lock()
+                .unwrap()
+                .insert(self.key.clone(), secret.to_vec());
+            Ok(())
+        }
+
+        fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+            match self.store.lock().unwrap().get(&self.key) {
+                Some(secret) => Ok(secret.clone()),
+                None => Err(keyring::Error::NoEntry),
+            }
+        }
+
+        fn delete_credential(&self) -> keyring::Result<()> {
+            match self.store.lock().unwrap().remove(&self.key) {
+                Some(_) => Ok(()),
+                None => Err(keyring::Error::NoEntry),
+            }
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn store_then_load_round_trips() {
+        keyring::set_default_credential_builder(Box::new(MemoryBuilder(Store::default())));
+
+        assert_eq!(load(CLIENT_ID_ENTRY), None);
+        store(CLIENT_ID_ENTRY, "client id").unwrap();
+        store(REFRESH_TOKEN_ENTRY, "refresh token").unwrap();
+        assert_eq!(load(CLIENT_ID_ENTRY).as_deref(), Some("client id"));
+        assert_eq!(load(REFRESH_TOKEN_ENTRY).as_deref(), Some("refresh token"));
+
+        delete(CLIENT_ID_ENTRY).unwrap();
+        delete(CLIENT_ID_ENTRY).unwrap();
+        assert_eq!(load(CLIENT_ID_ENTRY), None);
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::cache_dir;
+
+const TRANSFER_LOG_NAME: &str = "transfers.log";
+const ROTATED_TRANSFER_LOG_NAME: &str = "transfers.log.1";
+/// Size after which the log is moved aside and started afresh.
+const TRANSFER_LOG_MAX_BYTES: u64 = 100 * 1024 * 1024;
+
+pub enum TransferEvent {
+    Download {
+        path: String,
+        /// Revision downloaded instead of the current one, if any.
+        rev: Option<String>,
+        bytes: u64,
+        duration_ms: u128,
+        status: String,
+    },
+}
+
+fn log_line(event: TransferEvent, time: u64) -> Value {
+    match event {
+        TransferEvent::Download {
+            path,
+            rev,
+            bytes,
+            duration_ms,
+            status,
+        } => json!({
+            "time": time,
+            "direction": "download",
+            "path": path,
+            "rev": rev,
+            "bytes": bytes,
+            "duration_ms": duration_ms,
+            "status": status,
+        }),
+    }
+}
+
+/// Appends `event` as a JSON line to the transfer log in the cache directory,
+/// keeping one rotated log besides the current one.
+pub fn log_transfer(event: TransferEvent) -> Result<(), String> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    append_line(
+        &cache_dir()?,
+        &log_line(event, time),
+        TRANSFER_LOG_MAX_BYTES,
+    )
+}
+
+/// Appends `line` to the transfer log in `dir`, first moving the log aside if
+/// it has reached `max_bytes`.
+fn append_line(dir: &Path, line: &Value, max_bytes: u64) -> Result<(), String> {
+    let path = dir.join(TRANSFER_LOG_NAME);
+    if fs::metadata(&path).is_ok_and(|metadata| metadata.len() >= max_bytes) {
+        if let Err(error) = fs::rename(&path, dir.join(ROTATED_TRANSFER_LOG_NAME)) {
+            return Err(format!("Could not rotate the transfer log: {error}"));
+        }
+    }
+
+    let mut file = match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(file) => file,
+        Err(error) => return Err(format!("Could not open the transfer log: {error}")),
+    };
+    match writeln!(file, "{line}") {
+        Ok(()) => Ok(()),
+        Err(error) => Err(format!("Could not write the transfer log: {error}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    #[test]
+    fn logs_the_revision_apart_from_the_path() {
+        let event = TransferEvent::Download {
+            path: "/docs/a.txt".to_string(),
+            rev: Some("a1c10ce0dd78".to_string()),
+            bytes: 3,
+            duration_ms: 12,
+            status: "ok".to_string(),
+        };
+        let line = log_line(event, 1_700_000_000);
+
+        assert_eq!(line["path"], "/docs/a.txt");
+        assert_eq!(line["rev"], "a1c10ce0dd78");
+        assert_eq!(line["direction"], "download");
+        assert_eq!(line["time"], 1_700_000_000);
+    }
+
+    #[test]
+    fn rotates_the_log_once_it_is_full() {
+        let dir = mock::temp_path("transfer-log");
+        fs::create_dir_all(&dir).unwrap();
+        let first = json!({"path": "/first"});
+        let second = json!({"path": "/second"});
+        let max_bytes = first.to_string().len() as u64;
+
+        append_line(&dir, &first, max_bytes).unwrap();
+        append_line(&dir, &second, max_bytes).unwrap();
+        let current = fs::read_to_string(dir.join(TRANSFER_LOG_NAME)).unwrap();
+        let rotated = fs::read_to_string(dir.join(ROTATED_TRANSFER_LOG_NAME)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(rotated, format!("{first}\n"));
+        assert_eq!(current, format!("{second}\n"));
+    }
+}
@@ -0,0 +1,268 @@
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs the binary in a scratch directory with its own HOME, so no cached
+/// tokens or configuration from the machine leak into the test.
+fn rustyx(name: &str, config: Option<&str>, args: &[&str], envs: &[(&str, &str)]) -> Output {
+    let dir = scratch_dir(name, config);
+    let output = command(&dir)
+        .args(args)
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap();
+    let _ = fs::remove_dir_all(&dir);
+    output
+}
+
+fn scratch_dir(name: &str, config: Option<&str>) -> PathBuf {
+    let dir = env::temp_dir().join(format!("rustyx-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    if let Some(config) = config {
+        fs::write(dir.join("config.json"), config).unwrap();
+    }
+    dir
+}
+
+fn command(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rustyx"));
+    command
+        .current_dir(dir)
+        .env("HOME", dir)
+        .env_remove("RUSTYX_ACCESS_TOKEN")
+        .env_remove("RUSTYX_NONINTERACTIVE");
+    command
+}
+
+const CONFIG: &str = r#"{"CLIENT_ID": "id", "CLIENT_SECRET": "secret"}"#;
+
+#[test]
+fn non_interactive_login_fails_without_printing_the_url() {
+    let output = rustyx(
+        "non-interactive",
+        Some(CONFIG),
+        &["whoami", "--shell-export"],
+        &[("RUSTYX_NONINTERACTIVE", "1")],
+    );
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("non-interactive"), "{stderr}");
+    assert!(!stderr.contains("oauth2/authorize"), "{stderr}");
+}
+
+#[test]
+fn exit_codes_follow_the_error_category() {
+    let non_interactive = [("RUSTYX_NONINTERACTIVE", "1")];
+    let code = |name, config, args: &[&str], envs: &[(&str, &str)]| {
+        rustyx(name, config, args, envs).status.code()
+    };
+
+    assert_eq!(
+        code("auth", Some(CONFIG), &["whoami"], &non_interactive),
+        Some(3)
+    );
+    assert_eq!(code("usage", None, &["--no-such-option"], &[]), Some(2));
+    assert_eq!(code("help", None, &["--help"], &[]), Some(0));
+
+    let shadowing = r#"{"ALIASES": {"whoami": "resolve /a"}}"#;
+    assert_eq!(code("alias", Some(shadowing), &["whoami"], &[]), Some(2));
+}
+
+#[test]
+fn exit_codes_off_always_exits_with_1() {
+    let non_interactive = [("RUSTYX_NONINTERACTIVE", "1")];
+    let code = |name, config, args: &[&str], envs: &[(&str, &str)]| {
+        rustyx(name, config, args, envs).status.code()
+    };
+
+    assert_eq!(
+        code(
+            "auth-off",
+            Some(CONFIG),
+            &["--exit-codes", "off", "whoami"],
+            &non_interactive
+        ),
+        Some(1)
+    );
+    assert_eq!(
+        code(
+            "usage-off",
+            None,
+            &["--exit-codes=off", "--no-such-option"],
+            &[]
+        ),
+        Some(1)
+    );
+    assert_eq!(
+        code("help-off", None, &["--exit-codes", "off", "--help"], &[]),
+        Some(0)
+    );
+
+    let shadowing = r#"{"ALIASES": {"whoami": "resolve /a"}}"#;
+    assert_eq!(
+        code(
+            "alias-off",
+            Some(shadowing),
+            &["--exit-codes", "off", "whoami"],
+            &[]
+        ),
+        Some(1)
+    );
+}
+
+#[test]
+fn ctrl_c_at_the_login_prompt_exits() {
+    let dir = scratch_dir("ctrl-c", Some(CONFIG));
+    let mut child = command(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stderr = child.stderr.take().unwrap();
+    let mut output = Vec::new();
+    let mut buffer = [0; 1024];
+    while !String::from_utf8_lossy(&output).contains("Authorization code: ") {
+        let read = stderr.read(&mut buffer).unwrap();
+        assert!(read > 0, "{}", String::from_utf8_lossy(&output));
+        output.extend_from_slice(&buffer[..read]);
+    }
+    let interrupted = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(interrupted.success());
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() > Duration::from_secs(5) {
+            child.kill().unwrap();
+            panic!("still waiting for the authorization code after Ctrl-C");
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(status.code(), Some(130));
+}
+
+/// A proxy address that refuses every connection, as if offline: nothing
+/// listens on the port once the listener is dropped.
+fn offline_proxy() -> String {
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    format!("http://127.0.0.1:{port}")
+}
+
+#[test]
+fn network_wait_notices_stay_off_stdout() {
+    let proxy = offline_proxy();
+    let config =
+        r#"{"CLIENT_ID": "id", "CLIENT_SECRET": "secret", "NETWORK_WAIT_TIMEOUT_SECS": 1}"#;
+    let envs = [
+        ("HTTPS_PROXY", proxy.as_str()),
+        ("https_proxy", proxy.as_str()),
+    ];
+
+    let output = rustyx("wait", Some(config), &["whoami", "--shell-export"], &envs);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Waiting for network..."), "{stderr}");
+
+    let output = rustyx("wait-quiet", Some(config), &["--quiet", "whoami"], &envs);
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("Waiting for network..."), "{stderr}");
+}
+
+const ALIAS_CONFIG: &str = r#"{
+    "ALIASES": {"me": "whoami --shell-export", "aliases": "alias list"},
+    "DEFAULT_COMMAND": "aliases"
+}"#;
+
+#[test]
+fn lists_aliases_and_runs_the_default_command() {
+    let expected = "me = whoami --shell-export\naliases = alias list\nDefault command: aliases\n";
+
+    let output = rustyx("alias-list", Some(ALIAS_CONFIG), &["alias", "list"], &[]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    let output = rustyx("alias-default", Some(ALIAS_CONFIG), &["--"], &[]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
+
+#[test]
+fn quiet_alias_lists_print_nothing() {
+    let output = rustyx(
+        "alias-quiet",
+        Some(ALIAS_CONFIG),
+        &["--quiet", "alias", "list"],
+        &[],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn logout_forgets_the_login_and_the_root_namespace() {
+    let dir = scratch_dir("logout", Some(CONFIG));
+    let cache = dir.join(".cache").join("rustyx");
+    fs::create_dir_all(&cache).unwrap();
+    fs::write(cache.join("rustyx"), "refresh").unwrap();
+    fs::write(cache.join("root_namespace"), "100").unwrap();
+
+    let output = command(&dir).arg("logout").output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!cache.join("rustyx").exists());
+    assert!(!cache.join("root_namespace").exists());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn external_access_tokens_skip_the_login() {
+    // Without a configuration file logging in fails with exit code 3, so a
+    // network error shows the token went straight to the API.
+    let proxy = offline_proxy();
+    let envs = [
+        ("HTTPS_PROXY", proxy.as_str()),
+        ("https_proxy", proxy.as_str()),
+        ("RUSTYX_ACCESS_TOKEN", "env-token"),
+    ];
+    let output = rustyx("env-token", None, &["--non-interactive", "whoami"], &envs);
+    assert_eq!(output.status.code(), Some(4));
+
+    let dir = scratch_dir("stdin-token", None);
+    let mut child = command(&dir)
+        .args(["--non-interactive", "--access-token-stdin", "whoami"])
+        .envs(envs[..2].iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"stdin-token\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    let _ = fs::remove_dir_all(&dir);
+    assert_eq!(output.status.code(), Some(4));
+}